@@ -0,0 +1,83 @@
+use fuser::{FileAttr, FileType};
+use lru::LruCache;
+use std::ffi::OsString;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+// 目录列表，(inode, 类型, 名称)
+pub(crate) type DirEntries = Arc<Vec<(u64, FileType, OsString)>>;
+
+/// 属性和目录列表缓存的命中统计
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub attr_hits: u64,
+    pub attr_misses: u64,
+    pub dir_hits: u64,
+    pub dir_misses: u64,
+}
+
+impl CacheStats {
+    /// 属性缓存的命中率，没有查询过时为 0
+    pub fn attr_hit_rate(&self) -> f64 {
+        hit_rate(self.attr_hits, self.attr_misses)
+    }
+    /// 目录列表缓存的命中率，没有查询过时为 0
+    pub fn dir_hit_rate(&self) -> f64 {
+        hit_rate(self.dir_hits, self.dir_misses)
+    }
+}
+
+fn hit_rate(hits: u64, misses: u64) -> f64 {
+    if hits + misses == 0 {
+        0.0
+    } else {
+        hits as f64 / (hits + misses) as f64
+    }
+}
+
+// 提交的内容不会变化，按 inode 缓存提交中节点的属性和目录列表，容量为 0 时不缓存
+pub(crate) struct Cache {
+    attrs: Option<LruCache<u64, FileAttr>>,
+    dirs: Option<LruCache<u64, DirEntries>>,
+    stats: CacheStats,
+}
+
+impl Cache {
+    pub(crate) fn new(size: usize) -> Cache {
+        let size = NonZeroUsize::new(size);
+        Cache {
+            attrs: size.map(LruCache::new),
+            dirs: size.map(LruCache::new),
+            stats: CacheStats::default(),
+        }
+    }
+    pub(crate) fn stats(&self) -> CacheStats {
+        self.stats
+    }
+    pub(crate) fn attr(&mut self, ino: u64) -> Option<FileAttr> {
+        let attr = self.attrs.as_mut()?.get(&ino).copied();
+        match attr {
+            Some(_) => self.stats.attr_hits += 1,
+            None => self.stats.attr_misses += 1,
+        }
+        attr
+    }
+    pub(crate) fn put_attr(&mut self, attr: FileAttr) {
+        if let Some(attrs) = self.attrs.as_mut() {
+            attrs.put(attr.ino, attr);
+        }
+    }
+    pub(crate) fn dir(&mut self, ino: u64) -> Option<DirEntries> {
+        let entries = self.dirs.as_mut()?.get(&ino).cloned();
+        match entries {
+            Some(_) => self.stats.dir_hits += 1,
+            None => self.stats.dir_misses += 1,
+        }
+        entries
+    }
+    pub(crate) fn put_dir(&mut self, ino: u64, entries: DirEntries) {
+        if let Some(dirs) = self.dirs.as_mut() {
+            dirs.put(ino, entries);
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use gio::glib;
+use gio::prelude::*;
+
+use crate::CANCEL_NONE;
+
+// 打开的文件流，顺序读取时接着上次的位置继续读
+pub(crate) struct Cursor {
+    pub(crate) stream: gio::InputStream,
+    pub(crate) pos: u64,
+}
+
+impl Cursor {
+    pub(crate) fn new(stream: gio::InputStream) -> Cursor {
+        Cursor { stream, pos: 0 }
+    }
+    pub(crate) fn can_seek(&self) -> bool {
+        self.seekable().is_some()
+    }
+    fn seekable(&self) -> Option<&gio::Seekable> {
+        self.stream
+            .dynamic_cast_ref::<gio::Seekable>()
+            .filter(|seekable| seekable.can_seek())
+    }
+    // 定位到 offset，不支持 seek 的流（如 archive 仓库的解压流）只能向后跳过
+    pub(crate) fn seek(&mut self, offset: u64) -> Result<(), glib::Error> {
+        if self.pos == offset {
+            return Ok(());
+        }
+        if let Some(seekable) = self.seekable() {
+            seekable.seek(offset as i64, glib::SeekType::Set, CANCEL_NONE)?;
+            self.pos = offset;
+            return Ok(());
+        }
+        while self.pos < offset {
+            let skipped = self
+                .stream
+                .skip((offset - self.pos) as usize, CANCEL_NONE)?;
+            if skipped <= 0 {
+                break;
+            }
+            self.pos += skipped as u64;
+        }
+        Ok(())
+    }
+    // 读取最多 size 字节，只有到达文件末尾时才会少于 size
+    pub(crate) fn read(&mut self, size: usize) -> Result<Vec<u8>, glib::Error> {
+        let mut buf = vec![0; size];
+        let mut n = 0;
+        while n < size {
+            let len = self.stream.read(&mut buf[n..], CANCEL_NONE)?;
+            if len == 0 {
+                break;
+            }
+            n += len;
+        }
+        buf.truncate(n);
+        self.pos += n as u64;
+        Ok(buf)
+    }
+}
@@ -0,0 +1,29 @@
+use gio::glib;
+
+// dirtree 对象，类型为 (a(say)a(sayay))，依次是文件 (名称, 内容校验和) 和
+// 子目录 (名称, dirtree 校验和, dirmeta 校验和)，两个数组各自按名称排列
+pub(crate) struct DirTree(glib::Variant);
+
+impl DirTree {
+    pub(crate) fn load(repo: &ostree::Repo, checksum: &str) -> Result<DirTree, glib::Error> {
+        let tree = repo.load_variant(ostree::ObjectType::DirTree, checksum)?;
+        Ok(DirTree(tree))
+    }
+    // 文件的 (名称, 内容对象校验和)
+    pub(crate) fn files(&self) -> impl Iterator<Item = (String, String)> {
+        entries(self.0.child_value(0))
+    }
+    // 子目录的 (名称, dirtree 校验和)
+    pub(crate) fn dirs(&self) -> impl Iterator<Item = (String, String)> {
+        entries(self.0.child_value(1))
+    }
+}
+
+fn entries(array: glib::Variant) -> impl Iterator<Item = (String, String)> {
+    (0..array.n_children()).map(move |i| {
+        let entry = array.child_value(i);
+        let name = entry.child_value(0).str().unwrap_or("").to_string();
+        let checksum = ostree::checksum_from_bytes_v(&entry.child_value(1)).to_string();
+        (name, checksum)
+    })
+}
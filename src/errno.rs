@@ -0,0 +1,108 @@
+// GLib 错误到 errno 的映射
+use gio::glib;
+use libc::c_int;
+
+// OSTree 自身也使用 G_IO_ERROR，对象损坏等无法归类的错误为 G_IO_ERROR_FAILED，映射为 EIO；
+// archive 仓库中损坏的对象解压失败时是 G_IO_ERROR_INVALID_DATA，同样映射为 EIO
+pub(crate) fn errno(err: &glib::Error) -> c_int {
+    if let Some(kind) = err.kind::<gio::IOErrorEnum>() {
+        return io_errno(kind);
+    }
+    if let Some(kind) = err.kind::<glib::FileError>() {
+        return file_errno(kind);
+    }
+    libc::EIO
+}
+
+fn io_errno(kind: gio::IOErrorEnum) -> c_int {
+    use gio::IOErrorEnum::*;
+    match kind {
+        NotFound => libc::ENOENT,
+        Exists => libc::EEXIST,
+        IsDirectory => libc::EISDIR,
+        NotDirectory => libc::ENOTDIR,
+        NotEmpty => libc::ENOTEMPTY,
+        NotRegularFile | NotSymbolicLink | NotMountableFile => libc::EINVAL,
+        FilenameTooLong => libc::ENAMETOOLONG,
+        InvalidFilename | InvalidArgument => libc::EINVAL,
+        InvalidData => libc::EIO,
+        TooManyLinks => libc::ELOOP,
+        NoSpace => libc::ENOSPC,
+        NotSupported => libc::ENOTSUP,
+        PermissionDenied => libc::EACCES,
+        ReadOnly => libc::EROFS,
+        Cancelled => libc::EINTR,
+        TimedOut => libc::ETIMEDOUT,
+        Busy => libc::EBUSY,
+        WouldBlock => libc::EAGAIN,
+        TooManyOpenFiles => libc::EMFILE,
+        _ => libc::EIO,
+    }
+}
+
+fn file_errno(kind: glib::FileError) -> c_int {
+    use glib::FileError::*;
+    match kind {
+        Exist => libc::EEXIST,
+        Isdir => libc::EISDIR,
+        Acces => libc::EACCES,
+        Nametoolong => libc::ENAMETOOLONG,
+        Noent => libc::ENOENT,
+        Notdir => libc::ENOTDIR,
+        Nxio => libc::ENXIO,
+        Nodev => libc::ENODEV,
+        Rofs => libc::EROFS,
+        Txtbsy => libc::ETXTBSY,
+        Fault => libc::EFAULT,
+        Loop => libc::ELOOP,
+        Nospc => libc::ENOSPC,
+        Nomem => libc::ENOMEM,
+        Mfile => libc::EMFILE,
+        Nfile => libc::ENFILE,
+        Badf => libc::EBADF,
+        Inval => libc::EINVAL,
+        Pipe => libc::EPIPE,
+        Again => libc::EAGAIN,
+        Intr => libc::EINTR,
+        Perm => libc::EPERM,
+        Nosys => libc::ENOSYS,
+        _ => libc::EIO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn io(kind: gio::IOErrorEnum) -> c_int {
+        errno(&glib::Error::new(kind, "test"))
+    }
+
+    #[test]
+    fn io_errors() {
+        assert_eq!(io(gio::IOErrorEnum::NotFound), libc::ENOENT);
+        assert_eq!(io(gio::IOErrorEnum::PermissionDenied), libc::EACCES);
+        assert_eq!(io(gio::IOErrorEnum::NotDirectory), libc::ENOTDIR);
+        assert_eq!(io(gio::IOErrorEnum::IsDirectory), libc::EISDIR);
+        assert_eq!(io(gio::IOErrorEnum::Failed), libc::EIO);
+        assert_eq!(io(gio::IOErrorEnum::InvalidData), libc::EIO);
+    }
+
+    #[test]
+    fn file_errors() {
+        assert_eq!(
+            errno(&glib::Error::new(glib::FileError::Noent, "test")),
+            libc::ENOENT
+        );
+        assert_eq!(
+            errno(&glib::Error::new(glib::FileError::Acces, "test")),
+            libc::EACCES
+        );
+    }
+
+    #[test]
+    fn other_domains_are_eio() {
+        let err = glib::Error::new(glib::KeyFileError::NotFound, "test");
+        assert_eq!(errno(&err), libc::EIO);
+    }
+}
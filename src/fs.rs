@@ -0,0 +1,2158 @@
+use fuser::consts::{FOPEN_KEEP_CACHE, FUSE_DO_READDIRPLUS};
+use fuser::{
+    FileAttr, FileType, Filesystem, KernelConfig, ReplyAttr, ReplyCreate, ReplyData,
+    ReplyDirectory, ReplyDirectoryPlus, ReplyEmpty, ReplyEntry, ReplyOpen, ReplyStatfs, ReplyWrite,
+    ReplyXattr, Request, TimeOrNow,
+};
+use gio::glib;
+use gio::prelude::*;
+use gio::FileInfo;
+use libc::{c_int, EACCES, EBADF, EINVAL, EISDIR, ENODATA, ENOENT, ENOTDIR, ERANGE, EROFS};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, field, info, instrument, trace, warn, Span};
+
+use crate::cache::{Cache, CacheStats, DirEntries};
+use crate::cursor::Cursor;
+use crate::dirtree::DirTree;
+use crate::errno::errno;
+use crate::inode::{checksum_ino, count_links, path_ino};
+use crate::meta::{self, META, META_FILES};
+use crate::pool::Pool;
+use crate::usage::{self, Usage};
+use crate::{InodeMode, Options, CANCEL_NONE, FLAGS_NONE};
+
+// ref 目录、history 等虚拟目录的内容会随仓库变化，使用较短的 TTL
+const VIRTUAL_TTL: Duration = Duration::from_secs(1);
+
+// inode 对应的节点
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Node {
+    // 提交中的路径，root 是 Core::roots 的下标，path 相对提交根目录，根目录为空路径；
+    // 提交中的文件名不一定是 UTF-8
+    Tree { root: usize, path: PathBuf },
+    // 按 ref 名称分层的虚拟目录，prefix 为 "" 时是挂载点根目录
+    Refs { prefix: String },
+    // /by-commit，按校验和访问任意提交
+    ByCommit,
+    // /<ref>/history，第 n 个子目录是 ref 往前第 n 个提交
+    History { ref_: String },
+    // 提交根目录下的 .ostree，包含提交元数据生成的只读文件
+    Meta { root: usize },
+    MetaFile { root: usize, name: String },
+}
+
+// open/opendir 返回的句柄，fh 是 Shared::handles 的键
+enum Handle {
+    // 提交中的文件，保持打开的文件流
+    Stream(Cursor),
+    // .ostree 下的文件，打开时生成内容
+    Data(Vec<u8>),
+    // 打开目录时列出的内容，分页读取期间保持不变；parent 是 .. 的 inode
+    Dir { parent: u64, entries: DirEntries },
+}
+
+// 打开的句柄和它的条件变量，读取文件流后通知等待轮到自己的读取请求
+struct Slot {
+    handle: Mutex<Handle>,
+    moved: Condvar,
+}
+
+impl Slot {
+    fn lock(&self) -> MutexGuard<'_, Handle> {
+        self.handle.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// 不支持 seek 的流上，读取请求等待前面的请求读到自己 offset 的最长时间
+const READ_WAIT: Duration = Duration::from_millis(20);
+
+// 扩展属性的 (名称, 值) 列表
+type Xattrs = Vec<(Vec<u8>, Vec<u8>)>;
+
+const BY_COMMIT: &str = "by-commit";
+const HISTORY: &str = "history";
+
+// 挂载进文件系统的提交，首次访问时才读取
+struct Root {
+    // 提交根目录相对挂载点的路径
+    key: String,
+    // 提交根目录所在的目录，挂载点根目录为 None
+    parent: Option<Node>,
+    rev: String,
+    // 读取后 rev 对应的提交校验和
+    checksum: String,
+    file: Option<gio::File>,
+}
+
+// inode 表、已挂载的提交和缓存，由所有工作线程共享；只保存状态，不读取仓库
+struct Core {
+    inode_mode: InodeMode,
+    roots: Vec<Root>,
+    ino_map: HashMap<u64, Node>,
+    node_map: HashMap<Node, u64>,
+    ino_index: u64,
+    // checksum 模式下每个提交中各文件 inode 出现的次数，键是提交校验和，每个提交只统计一次
+    links: HashMap<String, Arc<HashMap<u64, u32>>>,
+    cache: Cache,
+    // 读取过的完整历史，键是最新提交的校验和；提交不会变化，ref 前进后按新的校验和重新读取
+    histories: HashMap<String, Arc<Vec<String>>>,
+    // statfs 统计过的空间占用，键是统计的提交校验和
+    usage: HashMap<Vec<String>, Usage>,
+}
+
+// 工作线程共享的状态。core 的锁只在读写 inode 表和缓存时持有，读取仓库在锁外进行；
+// 读取文件只锁住对应的句柄，大文件的读取不会阻塞其他进程浏览挂载点
+struct Shared {
+    repo: ostree::Repo,
+    options: Options,
+    // --all-refs 时为 Some，值表示是否列出远程 ref；挂载单个提交时为 None
+    remotes: Option<bool>,
+    core: Mutex<Core>,
+    handles: Mutex<HashMap<u64, Arc<Slot>>>,
+    next_fh: AtomicU64,
+}
+
+// SAFETY: 这里共享的 GObject 只有 OstreeRepo 和各提交根目录的 OstreeRepoFile，引用计数是
+// 原子操作。OstreeRepo 读取对象时不修改共享状态，内部的缓存有自己的锁。OstreeRepoFile 在
+// ensure_resolved 时惰性填充自身字段且不加锁，所以根目录在 root_file 中解析完成后才放入
+// Core，之后只会被读取；子文件由 resolve_relative_path 和 child 每次新建，只在创建它的线程中
+// 使用，它们的 ensure_resolved 只读取已解析的父目录，不会放入共享状态
+unsafe impl Send for Core {}
+unsafe impl Send for Shared {}
+unsafe impl Sync for Shared {}
+// SAFETY: 句柄只在自身的锁内访问，其中的输入流同一时间只被一个线程使用
+unsafe impl Send for Handle {}
+
+/// 只读的 OSTree 文件系统，实现了 fuser::Filesystem；请求在线程池中并发处理
+pub struct OstreeFs {
+    shared: Arc<Shared>,
+    // 挂载来源，仓库路径，挂载单个提交时加上 @<校验和>
+    source: String,
+    pool: Option<Pool>,
+}
+
+impl OstreeFs {
+    fn from_shared(shared: Shared, source: String) -> OstreeFs {
+        OstreeFs {
+            shared: Arc::new(shared),
+            source,
+            pool: None,
+        }
+    }
+    /// 挂载 repo 中的单个提交，rev 可以是 ref 或提交校验和，ref 在创建时解析为提交
+    pub fn new(repo: ostree::Repo, rev: &str, options: Options) -> Result<OstreeFs, glib::Error> {
+        let shared = Shared::new(repo, options, None);
+        let checksum = shared.resolve(rev)?;
+        let source = format!("{}@{}", repo_path(&shared.repo), checksum);
+        let root = shared.core().root_for("", &checksum, None);
+        shared.root_file(root)?;
+        shared.core().bind(
+            1,
+            Node::Tree {
+                root,
+                path: PathBuf::new(),
+            },
+        );
+        Ok(OstreeFs::from_shared(shared, source))
+    }
+    /// 根目录按 / 分层列出 repo 中的所有 ref，每个 ref 是它当前指向的提交的根目录，
+    /// ref 的增删和前进会反映在挂载点中；remotes 为 true 时也列出 remote:ref 形式的远程 ref
+    pub fn with_refs(
+        repo: ostree::Repo,
+        remotes: bool,
+        options: Options,
+    ) -> Result<OstreeFs, glib::Error> {
+        let source = repo_path(&repo);
+        let shared = Shared::new(repo, options, Some(remotes));
+        shared.list_refs()?;
+        shared.core().bind(
+            1,
+            Node::Refs {
+                prefix: "".to_string(),
+            },
+        );
+        Ok(OstreeFs::from_shared(shared, source))
+    }
+    /// 挂载来源，显示在 /proc/mounts 和 df 中
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+    /// 挂载选项
+    pub fn options(&self) -> &Options {
+        &self.shared.options
+    }
+    /// 根目录下当前列出的 ref，每次从仓库重新读取；只挂载单个提交时为空
+    pub fn refs(&self) -> Result<Vec<String>, glib::Error> {
+        Ok(self.shared.list_refs()?.into_keys().collect())
+    }
+    /// 属性和目录列表缓存的命中统计
+    pub fn cache_stats(&self) -> CacheStats {
+        self.shared.core().cache.stats()
+    }
+    // 在线程池中处理请求，线程池在挂载后创建
+    fn spawn<F: FnOnce(&Shared) + Send + 'static>(&mut self, f: F) {
+        let threads = self.shared.options.threads;
+        let pool = self.pool.get_or_insert_with(|| Pool::new(threads));
+        let shared = self.shared.clone();
+        pool.execute(move || f(&shared));
+    }
+}
+
+impl Core {
+    fn empty(options: &Options) -> Core {
+        Core {
+            inode_mode: options.inode_mode,
+            roots: vec![],
+            ino_map: HashMap::new(),
+            node_map: HashMap::new(),
+            ino_index: 1,
+            links: HashMap::new(),
+            cache: Cache::new(options.cache_size),
+            histories: HashMap::new(),
+            usage: HashMap::new(),
+        }
+    }
+    fn bind(&mut self, ino: u64, node: Node) {
+        // 共享 inode 的文件内容和元数据完全相同，记录第一次遇到的节点即可
+        self.ino_map.entry(ino).or_insert_with(|| node.clone());
+        self.node_map.insert(node, ino);
+    }
+    // 获取挂在 key 下的提交，不存在时登记一个新的；ref 前进后 <ref> 和 history/<n> 指向
+    // 另一个提交，登记为新的提交，之前分配的 inode 仍然指向原来的提交
+    fn root_for(&mut self, key: &str, rev: &str, parent: Option<Node>) -> usize {
+        if let Some(root) = self.roots.iter().position(|r| r.key == key && r.rev == rev) {
+            return root;
+        }
+        self.roots.push(Root {
+            key: key.to_string(),
+            parent,
+            rev: rev.to_string(),
+            checksum: "".to_string(),
+            file: None,
+        });
+        self.roots.len() - 1
+    }
+    // 节点所在的提交，虚拟目录不属于任何提交
+    fn node_root(&self, node: &Node) -> Option<usize> {
+        match node {
+            Node::Tree { root, .. } | Node::Meta { root } | Node::MetaFile { root, .. } => {
+                Some(*root)
+            }
+            Node::Refs { .. } | Node::ByCommit | Node::History { .. } => None,
+        }
+    }
+    // 节点相对挂载点的路径
+    fn node_path(&self, node: &Node) -> PathBuf {
+        let path = match node {
+            Node::Tree { root, path } => {
+                return join_os(Path::new(&self.roots[*root].key), path.as_os_str())
+            }
+            Node::Refs { prefix } => prefix.clone(),
+            Node::ByCommit => BY_COMMIT.to_string(),
+            Node::History { ref_ } => join_path(ref_, HISTORY),
+            Node::Meta { root } => join_path(&self.roots[*root].key, META),
+            Node::MetaFile { root, name } => {
+                join_path(&join_path(&self.roots[*root].key, META), name)
+            }
+        };
+        PathBuf::from(path)
+    }
+    fn ino_node(&self, ino: u64) -> Result<Node, glib::Error> {
+        self.ino_map
+            .get(&ino)
+            .cloned()
+            .ok_or_else(|| glib::Error::new(gio::IOErrorEnum::NotFound, "inode not found"))
+    }
+    // 获取节点的 inode，不存在时分配新的；checksum 模式下 checksum 是调用者由对象校验和
+    // 算出的 inode，没有时由路径计算
+    fn node_ino(&mut self, node: &Node, checksum: Option<u64>) -> u64 {
+        if let Some(ino) = self.node_map.get(node) {
+            return *ino;
+        }
+        let ino = match (self.inode_mode, checksum) {
+            (InodeMode::Sequential, _) => {
+                self.ino_index += 1;
+                self.ino_index
+            }
+            (InodeMode::Checksum, Some(ino)) => ino,
+            (InodeMode::Checksum, None) => self.location_ino(node),
+        };
+        self.bind(ino, node.clone());
+        ino
+    }
+    // 没有对象校验和的节点由路径计算 inode；提交中的节点再加上提交的 rev，
+    // 同一位置先后挂载的不同提交不共享 inode
+    fn location_ino(&self, node: &Node) -> u64 {
+        let mut key = self.node_path(node).into_os_string();
+        if let Some(root) = self.node_root(node) {
+            key.push("\0");
+            key.push(&self.roots[root].rev);
+        }
+        path_ino(Path::new(&key))
+    }
+}
+
+impl Shared {
+    fn new(repo: ostree::Repo, options: Options, remotes: Option<bool>) -> Shared {
+        Shared {
+            core: Mutex::new(Core::empty(&options)),
+            repo,
+            options,
+            remotes,
+            handles: Mutex::new(HashMap::new()),
+            next_fh: AtomicU64::new(1),
+        }
+    }
+    // 锁住 core，持有锁的线程 panic 后仍然可以继续使用；持有锁时不能再调用会加锁的方法
+    fn core(&self) -> MutexGuard<'_, Core> {
+        self.core.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    fn handles(&self) -> MutexGuard<'_, HashMap<u64, Arc<Slot>>> {
+        self.handles.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    fn add_handle(&self, handle: Handle) -> u64 {
+        let fh = self.next_fh.fetch_add(1, Ordering::Relaxed);
+        let slot = Slot {
+            handle: Mutex::new(handle),
+            moved: Condvar::new(),
+        };
+        self.handles().insert(fh, Arc::new(slot));
+        fh
+    }
+    fn handle(&self, fh: u64) -> Option<Arc<Slot>> {
+        self.handles().get(&fh).cloned()
+    }
+    fn remove_handle(&self, fh: u64) {
+        self.handles().remove(&fh);
+    }
+    // 在当前操作的 span 上记录节点路径，span 未启用时不拼接路径
+    fn record_path(&self, node: &Node) {
+        let span = Span::current();
+        if !span.is_disabled() {
+            let path = self.core().node_path(node);
+            span.record("path", field::display(path.display()));
+        }
+    }
+    // 在当前操作的 span 上记录 inode 的路径，span 未启用时不加锁
+    fn record_ino(&self, ino: u64) {
+        if Span::current().is_disabled() {
+            return;
+        }
+        let node = self.core().ino_node(ino);
+        if let Ok(node) = node {
+            self.record_path(&node);
+        }
+    }
+    // 读取提交的根目录，读取在锁外进行，多个线程同时读取同一个提交时使用先完成的结果
+    fn root_file(&self, root: usize) -> Result<gio::File, glib::Error> {
+        let rev = {
+            let core = self.core();
+            if let Some(file) = &core.roots[root].file {
+                return Ok(file.clone());
+            }
+            core.roots[root].rev.clone()
+        };
+        let (file, checksum) = self.repo.read_commit(&rev, CANCEL_NONE)?;
+        // 放入 Core 之前解析根目录，之后其他线程只会读取它
+        if let Some(f) = file.downcast_ref::<ostree::RepoFile>() {
+            f.ensure_resolved()?;
+        }
+        let mut core = self.core();
+        let entry = &mut core.roots[root];
+        if let Some(file) = &entry.file {
+            return Ok(file.clone());
+        }
+        info!(rev = %rev, commit = %checksum, "resolved commit");
+        entry.checksum = checksum.to_string();
+        entry.file = Some(file.clone());
+        Ok(file)
+    }
+    // 提交中各文件 inode 出现的次数，不是 checksum 模式时为 None。统计要遍历整个提交，
+    // 第一次需要提交中非目录文件的属性时才进行，只访问提交根目录（如列出 history 和
+    // by-commit）时不统计；同一个提交可能挂在多个位置，例如 <ref>、<ref>/history/0 和
+    // by-commit/<校验和>，只统计一次
+    fn root_links(&self, root: usize) -> Result<Option<Arc<HashMap<u64, u32>>>, glib::Error> {
+        if self.options.inode_mode != InodeMode::Checksum {
+            return Ok(None);
+        }
+        let file = self.root_file(root)?;
+        let checksum = self.core().roots[root].checksum.clone();
+        let cached = self.core().links.get(&checksum).cloned();
+        if cached.is_some() {
+            return Ok(cached);
+        }
+        let dirtree = file
+            .downcast_ref::<ostree::RepoFile>()
+            .and_then(|f| f.tree_get_contents_checksum())
+            .ok_or_else(|| {
+                glib::Error::new(gio::IOErrorEnum::NotFound, "commit has no root dirtree")
+            })?;
+        let mut links = HashMap::new();
+        count_links(&self.repo, &dirtree, &mut links)?;
+        let links = self
+            .core()
+            .links
+            .entry(checksum)
+            .or_insert_with(|| Arc::new(links))
+            .clone();
+        Ok(Some(links))
+    }
+    fn root_checksum(&self, root: usize) -> Result<String, glib::Error> {
+        self.root_file(root)?;
+        Ok(self.core().roots[root].checksum.clone())
+    }
+    // 获取节点对应的文件，虚拟目录没有对应的文件
+    fn node_file(&self, node: &Node) -> Result<gio::File, glib::Error> {
+        match node {
+            Node::Tree { root, path } => {
+                let file = self.root_file(*root)?;
+                if path.as_os_str().is_empty() {
+                    Ok(file)
+                } else {
+                    Ok(file.resolve_relative_path(path))
+                }
+            }
+            _ => Err(glib::Error::new(
+                gio::IOErrorEnum::IsDirectory,
+                "virtual directory",
+            )),
+        }
+    }
+    // 获取 inode 对应的文件
+    fn ino_file(&self, ino: u64) -> Result<gio::File, glib::Error> {
+        let node = self.core().ino_node(ino)?;
+        self.node_file(&node)
+    }
+    // 提交中文件和目录的 inode，checksum 模式下由对象校验和计算，读取校验和在锁外进行
+    fn tree_ino(&self, node: &Node, file: &gio::File, info: &FileInfo) -> u64 {
+        let path = {
+            let mut core = self.core();
+            if core.inode_mode == InodeMode::Sequential || core.node_map.contains_key(node) {
+                return core.node_ino(node, None);
+            }
+            core.node_path(node)
+        };
+        let ino = checksum_ino(&path, file, info);
+        self.core().node_ino(node, Some(ino))
+    }
+    // 获取节点属性，同时确定节点的 inode
+    fn node_attr(&self, node: &Node) -> Result<FileAttr, glib::Error> {
+        // 提交中的节点和 .ostree 下的文件不会变化，可以缓存
+        let cached = matches!(node, Node::Tree { .. } | Node::MetaFile { .. });
+        if cached {
+            let mut core = self.core();
+            let ino = core.node_map.get(node).copied();
+            if let Some(attr) = ino.and_then(|ino| core.cache.attr(ino)) {
+                return Ok(attr);
+            }
+        }
+        let attr = self.load_attr(node)?;
+        if cached {
+            self.core().cache.put_attr(attr);
+        }
+        Ok(attr)
+    }
+    // 提交中的内容不会变化，使用挂载选项中的 TTL；<ref> 和 history/<n> 本身随 ref 前进
+    // 指向另一个提交，和虚拟目录一样很快重新查询
+    fn node_ttl(&self, node: &Node) -> Duration {
+        match node {
+            Node::Tree { root, path } => {
+                let parent = self.core().roots[*root].parent.clone();
+                let moves = path.as_os_str().is_empty()
+                    && matches!(parent, Some(Node::Refs { .. } | Node::History { .. }));
+                if moves {
+                    VIRTUAL_TTL
+                } else {
+                    self.options.ttl
+                }
+            }
+            Node::Meta { .. } | Node::MetaFile { .. } => self.options.ttl,
+            Node::Refs { .. } | Node::ByCommit | Node::History { .. } => VIRTUAL_TTL,
+        }
+    }
+    fn load_attr(&self, node: &Node) -> Result<FileAttr, glib::Error> {
+        match node {
+            Node::Tree { root, path } => {
+                let file = self.node_file(node)?;
+                let info = file.query_info("", FLAGS_NONE, CANCEL_NONE)?;
+                // 不同 ref 可能指向同一个提交，提交根目录按挂载位置分配 inode
+                let ino = if path.as_os_str().is_empty() {
+                    self.core().node_ino(node, None)
+                } else {
+                    self.tree_ino(node, &file, &info)
+                };
+                let links = if file_kind(&info) == FileType::Directory {
+                    None
+                } else {
+                    self.root_links(*root)?
+                };
+                let mut attr = self.info2attr(&info, ino, links.as_deref());
+                if attr.kind == FileType::Directory {
+                    attr.nlink = self.tree_nlink(*root, path, &file)?;
+                }
+                Ok(attr)
+            }
+            Node::MetaFile { root, name } => {
+                let size = self.meta_file(*root, name)?.len() as u64;
+                let ino = self.core().node_ino(node, None);
+                let mut attr = self.dir_attr(ino);
+                attr.kind = FileType::RegularFile;
+                attr.perm = 0o444;
+                attr.size = size;
+                attr.blocks = size.div_ceil(512);
+                attr.nlink = 1;
+                Ok(attr)
+            }
+            // .ostree 下只有文件
+            Node::Meta { .. } => {
+                let ino = self.core().node_ino(node, None);
+                Ok(self.dir_attr(ino))
+            }
+            Node::Refs { .. } | Node::ByCommit | Node::History { .. } => {
+                let subdirs = self
+                    .dir_entries(node)?
+                    .iter()
+                    .filter(|(_, kind, _)| *kind == FileType::Directory)
+                    .count();
+                let ino = self.core().node_ino(node, None);
+                let mut attr = self.dir_attr(ino);
+                attr.nlink = 2 + subdirs as u32;
+                Ok(attr)
+            }
+        }
+    }
+    // 目录的硬链接数是 2 加上子目录数，子目录从 dirtree 中读取；
+    // 提交根目录还有 .ostree 和 history 两个虚拟目录，与同名的真实目录只算一个
+    fn tree_nlink(&self, root: usize, path: &Path, file: &gio::File) -> Result<u32, glib::Error> {
+        let dirtree = file
+            .downcast_ref::<ostree::RepoFile>()
+            .and_then(|f| {
+                f.ensure_resolved().ok()?;
+                f.tree_get_contents_checksum()
+            })
+            .ok_or_else(|| {
+                glib::Error::new(gio::IOErrorEnum::NotFound, "directory has no dirtree")
+            })?;
+        let names: Vec<String> = DirTree::load(&self.repo, &dirtree)?
+            .dirs()
+            .map(|(name, _)| name)
+            .collect();
+        let mut nlink = 2 + names.len() as u32;
+        if path.as_os_str().is_empty() {
+            let mut extra = vec![META];
+            if self.is_ref_root(&self.core(), root) {
+                extra.push(HISTORY);
+            }
+            nlink += extra
+                .iter()
+                .filter(|d| !names.iter().any(|n| n == *d))
+                .count() as u32;
+        }
+        Ok(nlink)
+    }
+    // 节点所在的目录，挂载点根目录的上级是它自己；history 的上级是 ref 的根目录，
+    // ref_checksum 是调用者解析出的 ref 当前指向的提交
+    fn parent_node(&self, core: &mut Core, node: &Node, ref_checksum: Option<&str>) -> Node {
+        match node {
+            Node::Tree { root, path } => match path.parent() {
+                Some(parent) => Node::Tree {
+                    root: *root,
+                    path: parent.to_path_buf(),
+                },
+                None => core.roots[*root]
+                    .parent
+                    .clone()
+                    .unwrap_or_else(|| node.clone()),
+            },
+            Node::Refs { prefix } => Node::Refs {
+                prefix: prefix.rsplit_once('/').map_or("", |(p, _)| p).to_string(),
+            },
+            Node::ByCommit => Node::Refs {
+                prefix: "".to_string(),
+            },
+            Node::History { ref_ } => {
+                let prefix = ref_.rsplit_once('/').map_or("", |(p, _)| p);
+                let parent = Node::Refs {
+                    prefix: prefix.to_string(),
+                };
+                match ref_checksum {
+                    Some(checksum) => Node::Tree {
+                        root: core.root_for(ref_, checksum, Some(parent)),
+                        path: PathBuf::new(),
+                    },
+                    None => parent,
+                }
+            }
+            Node::Meta { root } | Node::MetaFile { root, .. } => Node::Tree {
+                root: *root,
+                path: PathBuf::new(),
+            },
+        }
+    }
+    // 上级目录的 inode，上级目录通常已经被访问过，没有时查询它的属性来分配 inode
+    fn parent_ino(&self, node: &Node) -> Result<u64, glib::Error> {
+        // 在锁外解析 ref
+        let ref_checksum = match node {
+            Node::History { ref_ } => Some(self.resolve(ref_)?),
+            _ => None,
+        };
+        let parent = {
+            let mut core = self.core();
+            let parent = self.parent_node(&mut core, node, ref_checksum.as_deref());
+            if let Some(ino) = core.node_map.get(&parent) {
+                return Ok(*ino);
+            }
+            parent
+        };
+        Ok(self.node_attr(&parent)?.ino)
+    }
+    // 获取目录下名为 name 的节点
+    fn child_node(&self, parent: &Node, name: &OsStr) -> Result<Node, glib::Error> {
+        let not_found = || glib::Error::new(gio::IOErrorEnum::NotFound, "no such entry");
+        // 只有提交中的文件名可能不是 UTF-8，虚拟目录下的名称都是 UTF-8
+        let utf8 = name.to_str();
+        match parent {
+            Node::Tree { root, path } => {
+                if path.as_os_str().is_empty() && name == HISTORY {
+                    let core = self.core();
+                    if self.is_ref_root(&core, *root) {
+                        return Ok(Node::History {
+                            ref_: core.roots[*root].key.clone(),
+                        });
+                    }
+                }
+                if path.as_os_str().is_empty() && name == META {
+                    return Ok(Node::Meta { root: *root });
+                }
+                Ok(Node::Tree {
+                    root: *root,
+                    path: join_os(path, name),
+                })
+            }
+            Node::Refs { prefix } => {
+                let name = utf8.ok_or_else(not_found)?;
+                if prefix.is_empty() && name == BY_COMMIT {
+                    return Ok(Node::ByCommit);
+                }
+                let refs = self.list_refs()?;
+                let child = self.ref_child(&refs, &mut self.core(), prefix, name);
+                child.ok_or_else(not_found)
+            }
+            Node::ByCommit => {
+                let name = utf8.ok_or_else(not_found)?;
+                ostree::validate_checksum_string(name)?;
+                // 确认提交在本地存在
+                self.repo.load_commit(name)?;
+                let key = join_path(BY_COMMIT, name);
+                let root = self.core().root_for(&key, name, Some(Node::ByCommit));
+                Ok(Node::Tree {
+                    root,
+                    path: PathBuf::new(),
+                })
+            }
+            Node::History { ref_ } => {
+                let name = utf8.ok_or_else(not_found)?;
+                // 只接受规范写法，避免 01 和 1 成为同一目录的两个名字
+                let n = name.parse::<usize>().map_err(|_| not_found())?;
+                if n.to_string() != name {
+                    return Err(not_found());
+                }
+                let history = self.history(ref_, n)?;
+                let checksum = history.get(n).ok_or_else(not_found)?;
+                let key = join_path(&join_path(ref_, HISTORY), name);
+                let parent = Node::History { ref_: ref_.clone() };
+                let root = self.core().root_for(&key, checksum, Some(parent));
+                Ok(Node::Tree {
+                    root,
+                    path: PathBuf::new(),
+                })
+            }
+            Node::Meta { root } => {
+                let name = utf8.ok_or_else(not_found)?;
+                if !META_FILES.contains(&name) {
+                    return Err(not_found());
+                }
+                Ok(Node::MetaFile {
+                    root: *root,
+                    name: name.to_string(),
+                })
+            }
+            Node::MetaFile { .. } => Err(glib::Error::new(
+                gio::IOErrorEnum::NotDirectory,
+                "not a directory",
+            )),
+        }
+    }
+    // 生成 .ostree 下文件的内容
+    fn meta_file(&self, root: usize, name: &str) -> Result<Vec<u8>, glib::Error> {
+        let checksum = self.root_checksum(root)?;
+        let (commit, _) = self.repo.load_commit(&checksum)?;
+        let text = meta::commit_file(&commit, &checksum, name)
+            .ok_or_else(|| glib::Error::new(gio::IOErrorEnum::NotFound, "no such metadata file"))?;
+        Ok(text.into_bytes())
+    }
+    // 是否是 --all-refs 下某个 ref 的根目录
+    fn is_ref_root(&self, core: &Core, root: usize) -> bool {
+        matches!(core.roots[root].parent, Some(Node::Refs { .. }))
+    }
+    // prefix 下名为 name 的节点，同时是 ref 和其他 ref 前缀的名称按 ref 处理；ref 挂载它
+    // 当前指向的提交，和 history/<n> 一样按校验和登记
+    fn ref_child(
+        &self,
+        refs: &BTreeMap<String, String>,
+        core: &mut Core,
+        prefix: &str,
+        name: &str,
+    ) -> Option<Node> {
+        let full = join_path(prefix, name);
+        if let Some(checksum) = refs.get(&full) {
+            let parent = Node::Refs {
+                prefix: prefix.to_string(),
+            };
+            let root = core.root_for(&full, checksum, Some(parent));
+            return Some(Node::Tree {
+                root,
+                path: PathBuf::new(),
+            });
+        }
+        let dir = format!("{}/", full);
+        let is_prefix = refs
+            .range(dir.clone()..)
+            .next()
+            .is_some_and(|(r, _)| r.starts_with(&dir));
+        if is_prefix {
+            return Some(Node::Refs { prefix: full });
+        }
+        None
+    }
+    // rev 在本地能找到的历史提交，第 0 个是 rev 本身，沿 parent 往前直到本地缺失的提交；
+    // 读到第 depth 个就停止，读完整条历史时按最新提交缓存
+    fn history(&self, rev: &str, depth: usize) -> Result<Arc<Vec<String>>, glib::Error> {
+        let tip = self.resolve(rev)?;
+        let cached = self.core().histories.get(&tip).cloned();
+        if let Some(history) = cached {
+            return Ok(history);
+        }
+        let mut checksum = tip.clone();
+        let mut history = vec![];
+        while let Ok((commit, _)) = self.repo.load_commit(&checksum) {
+            history.push(checksum);
+            if history.len() > depth {
+                return Ok(Arc::new(history));
+            }
+            match ostree::commit_get_parent(&commit) {
+                Some(parent) => checksum = parent.to_string(),
+                None => break,
+            }
+        }
+        let history = Arc::new(history);
+        self.core().histories.insert(tip, history.clone());
+        Ok(history)
+    }
+    // 仓库中当前的 ref 和它们指向的提交，每次重新读取；挂载单个提交时为空
+    fn list_refs(&self) -> Result<BTreeMap<String, String>, glib::Error> {
+        let Some(remotes) = self.remotes else {
+            return Ok(BTreeMap::new());
+        };
+        let refs = self.repo.list_refs(None, CANCEL_NONE)?;
+        Ok(refs
+            .into_iter()
+            .filter(|(r, _)| remotes || !r.contains(':'))
+            .collect())
+    }
+    fn resolve(&self, rev: &str) -> Result<String, glib::Error> {
+        let checksum = self
+            .repo
+            .resolve_rev(rev, false)?
+            .ok_or_else(|| glib::Error::new(gio::IOErrorEnum::NotFound, "ref not found"))?;
+        Ok(checksum.to_string())
+    }
+    // 列出目录内容，返回 (inode, 类型, 名称)，提交中的目录会被缓存
+    fn dir_entries(&self, node: &Node) -> Result<DirEntries, glib::Error> {
+        let cached = matches!(node, Node::Tree { .. });
+        if cached {
+            let mut core = self.core();
+            let ino = core.node_map.get(node).copied();
+            if let Some(entries) = ino.and_then(|ino| core.cache.dir(ino)) {
+                return Ok(entries);
+            }
+        }
+        let entries = Arc::new(self.list_dir(node)?);
+        if cached {
+            let mut core = self.core();
+            if let Some(ino) = core.node_map.get(node).copied() {
+                core.cache.put_dir(ino, entries.clone());
+            }
+        }
+        Ok(entries)
+    }
+    fn list_dir(&self, node: &Node) -> Result<Vec<(u64, FileType, OsString)>, glib::Error> {
+        let mut entries = vec![];
+        match node {
+            Node::Refs { prefix } => {
+                let refs = self.list_refs()?;
+                let mut core = self.core();
+                if prefix.is_empty() {
+                    let ino = core.node_ino(&Node::ByCommit, None);
+                    entries.push((ino, FileType::Directory, BY_COMMIT.into()));
+                }
+                let mut names = BTreeSet::new();
+                for r in refs.keys() {
+                    let rest = if prefix.is_empty() {
+                        Some(r.as_str())
+                    } else {
+                        r.strip_prefix(prefix.as_str())
+                            .and_then(|r| r.strip_prefix('/'))
+                    };
+                    if let Some(rest) = rest {
+                        names.insert(rest.split('/').next().unwrap_or(rest).to_string());
+                    }
+                }
+                for name in names {
+                    if prefix.is_empty() && name == BY_COMMIT {
+                        continue;
+                    }
+                    if let Some(child) = self.ref_child(&refs, &mut core, prefix, &name) {
+                        let ino = core.node_ino(&child, None);
+                        entries.push((ino, FileType::Directory, name.into()));
+                    }
+                }
+            }
+            Node::Tree { root, path } => {
+                // 提交根目录下额外有 .ostree 目录，ref 的根目录下还有 history 目录，
+                // 覆盖提交中的同名文件
+                let (base, history) = {
+                    let mut core = self.core();
+                    if path.as_os_str().is_empty() {
+                        let ino = core.node_ino(&Node::Meta { root: *root }, None);
+                        entries.push((ino, FileType::Directory, META.into()));
+                    }
+                    let history = path.as_os_str().is_empty() && self.is_ref_root(&core, *root);
+                    if history {
+                        let child = Node::History {
+                            ref_: core.roots[*root].key.clone(),
+                        };
+                        let ino = core.node_ino(&child, None);
+                        entries.push((ino, FileType::Directory, HISTORY.into()));
+                    }
+                    (core.node_path(node), history)
+                };
+                // 列出目录和读取对象校验和在锁外进行
+                let file = self.node_file(node)?;
+                let mut children = vec![];
+                for info in file.enumerate_children("", FLAGS_NONE, CANCEL_NONE)? {
+                    let info = info?;
+                    let name = info.name().into_os_string();
+                    if (path.as_os_str().is_empty() && name == META) || (history && name == HISTORY)
+                    {
+                        continue;
+                    }
+                    let child = Node::Tree {
+                        root: *root,
+                        path: join_os(path, &name),
+                    };
+                    children.push((child, name, info));
+                }
+                let files = children
+                    .iter()
+                    .any(|(_, _, info)| file_kind(info) != FileType::Directory);
+                let links = if files { self.root_links(*root)? } else { None };
+                let known: Vec<bool> = {
+                    let core = self.core();
+                    children
+                        .iter()
+                        .map(|(child, _, _)| core.node_map.contains_key(child))
+                        .collect()
+                };
+                let checksum = self.options.inode_mode == InodeMode::Checksum;
+                let inos: Vec<Option<u64>> = children
+                    .iter()
+                    .zip(known)
+                    .map(|((_, name, info), known)| {
+                        (checksum && !known)
+                            .then(|| checksum_ino(&join_os(&base, name), &file.child(name), info))
+                    })
+                    .collect();
+                // 顺便缓存文件的属性，ls -l 随后的 lookup 不用再查询；
+                // 目录的链接数要读取它的 dirtree，等 lookup 时再计算
+                let mut core = self.core();
+                for ((child, name, info), ino) in children.into_iter().zip(inos) {
+                    let ino = core.node_ino(&child, ino);
+                    let attr = self.info2attr(&info, ino, links.as_deref());
+                    if attr.kind != FileType::Directory {
+                        core.cache.put_attr(attr);
+                    }
+                    entries.push((ino, attr.kind, name));
+                }
+            }
+            // 没有列出所有提交，只列出已经访问过的
+            Node::ByCommit => {
+                let mut core = self.core();
+                let prefix = format!("{}/", BY_COMMIT);
+                let checksums: Vec<String> = core
+                    .roots
+                    .iter()
+                    .filter_map(|r| r.key.strip_prefix(prefix.as_str()))
+                    .map(|c| c.to_string())
+                    .collect();
+                for checksum in checksums {
+                    let key = join_path(BY_COMMIT, &checksum);
+                    let root = core.root_for(&key, &checksum, Some(Node::ByCommit));
+                    let child = Node::Tree {
+                        root,
+                        path: PathBuf::new(),
+                    };
+                    let ino = core.node_ino(&child, None);
+                    entries.push((ino, FileType::Directory, checksum.into()));
+                }
+            }
+            Node::History { ref_ } => {
+                let history = self.history(ref_, usize::MAX)?;
+                let mut core = self.core();
+                for (n, checksum) in history.iter().enumerate() {
+                    let key = join_path(&join_path(ref_, HISTORY), &n.to_string());
+                    let parent = Node::History { ref_: ref_.clone() };
+                    let root = core.root_for(&key, checksum, Some(parent));
+                    let child = Node::Tree {
+                        root,
+                        path: PathBuf::new(),
+                    };
+                    let ino = core.node_ino(&child, None);
+                    entries.push((ino, FileType::Directory, n.to_string().into()));
+                }
+            }
+            Node::Meta { root } => {
+                let mut core = self.core();
+                for name in META_FILES {
+                    let child = Node::MetaFile {
+                        root: *root,
+                        name: name.to_string(),
+                    };
+                    let ino = core.node_ino(&child, None);
+                    entries.push((ino, FileType::RegularFile, name.into()));
+                }
+            }
+            Node::MetaFile { .. } => {
+                return Err(glib::Error::new(
+                    gio::IOErrorEnum::NotDirectory,
+                    "not a directory",
+                ))
+            }
+        }
+        sort_entries(&mut entries);
+        Ok(entries)
+    }
+    // 根据提交中的文件信息生成文件属性，uid/gid 按挂载选项映射；links 是文件所在提交的
+    // 硬链接数，目录的链接数由调用者通过 tree_nlink 计算
+    fn info2attr(
+        &self,
+        info: &gio::FileInfo,
+        ino: u64,
+        links: Option<&HashMap<u64, u32>>,
+    ) -> FileAttr {
+        let kind = file_kind(info);
+        // 符号链接的大小是目标路径的长度，设备文件、管道和套接字没有内容
+        let size = match kind {
+            FileType::Directory => 4096,
+            FileType::Symlink => info
+                .symlink_target()
+                .map_or(0, |target| target.as_os_str().len() as u64),
+            FileType::RegularFile => info.size() as u64,
+            _ => 0,
+        };
+        // checksum 模式下共享内容对象的文件共享 inode，链接数是对象在所在提交中被引用的次数，
+        // 不随其他提交的挂载和访问顺序变化；对象出现在多个提交中时内核只保留先查询到的值
+        let nlink = match kind {
+            FileType::Directory => 2,
+            _ => links.and_then(|l| l.get(&ino)).copied().unwrap_or(1),
+        };
+        let perm = if info.has_attribute("unix::mode") {
+            (info.attribute_uint32("unix::mode") & 0o7777) as u16
+        } else {
+            match info.file_type() {
+                gio::FileType::Directory => 0o755,
+                gio::FileType::SymbolicLink => 0o777,
+                _ => 0o644,
+            }
+        };
+        FileAttr {
+            ino,
+            size,
+            blksize: 512,
+            blocks: size.div_ceil(512),
+            atime: info.modification_time(),
+            mtime: info.modification_time(),
+            ctime: info.modification_time(),
+            kind,
+            crtime: info.modification_time(),
+            perm,
+            nlink,
+            uid: self.map_uid(info.attribute_uint32("unix::uid")),
+            gid: self.map_gid(info.attribute_uint32("unix::gid")),
+            rdev: match kind {
+                FileType::CharDevice | FileType::BlockDevice => info.attribute_uint32("unix::rdev"),
+                _ => 0,
+            },
+            flags: 0,
+        }
+    }
+    // 虚拟目录的属性，属于 root 且只读
+    fn dir_attr(&self, ino: u64) -> FileAttr {
+        FileAttr {
+            ino,
+            size: 4096,
+            blksize: 512,
+            blocks: 8,
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind: FileType::Directory,
+            perm: 0o555,
+            nlink: 2,
+            uid: self.map_uid(0),
+            gid: self.map_gid(0),
+            rdev: 0,
+            flags: 0,
+        }
+    }
+    fn map_uid(&self, uid: u32) -> u32 {
+        *self.options.uid_map.get(&uid).unwrap_or(&uid)
+    }
+    fn map_gid(&self, gid: u32) -> u32 {
+        *self.options.gid_map.get(&gid).unwrap_or(&gid)
+    }
+    // 读取 inode 对应 ostree 对象的扩展属性，返回 (名称, 值) 列表
+    fn xattrs(&self, ino: u64) -> Result<Xattrs, glib::Error> {
+        let node = self.core().ino_node(ino)?;
+        if !matches!(node, Node::Tree { .. }) {
+            return Ok(vec![]);
+        }
+        let f = self.node_file(&node)?;
+        let f = f
+            .downcast::<ostree::RepoFile>()
+            .map_err(|_| glib::Error::new(gio::IOErrorEnum::NotSupported, "not an ostree file"))?;
+        // 类型为 a(ayay)，名称是带结尾 \0 的字节串
+        let xattrs = f.xattrs(CANCEL_NONE)?;
+        let mut list = vec![];
+        for i in 0..xattrs.n_children() {
+            let entry = xattrs.child_value(i);
+            let name = entry.child_value(0);
+            let name = name.data();
+            let name = name.strip_suffix(&[0]).unwrap_or(name);
+            list.push((name.to_vec(), entry.child_value(1).data().to_vec()));
+        }
+        Ok(list)
+    }
+    // 打开节点对应的文件，文件流在锁外打开
+    fn open_handle(&self, node: Node) -> Result<Handle, glib::Error> {
+        match node {
+            Node::MetaFile { root, name } => Ok(Handle::Data(self.meta_file(root, &name)?)),
+            node => {
+                let f = self.node_file(&node)?;
+                Ok(Handle::Stream(open_stream(&f)?))
+            }
+        }
+    }
+    // 统计 inode 所在提交的空间占用，虚拟目录统计所有 ref 的提交；遍历提交在锁外进行
+    fn usage(&self, ino: u64) -> Result<Usage, glib::Error> {
+        let root = {
+            let core = self.core();
+            let node = core.ino_node(ino)?;
+            core.node_root(&node)
+        };
+        // ref 会前进，按统计的提交缓存
+        let commits: Vec<String> = match root {
+            Some(root) => vec![self.root_checksum(root)?],
+            None => self.list_refs()?.into_values().collect(),
+        };
+        let cached = self.core().usage.get(&commits).copied();
+        if let Some(usage) = cached {
+            return Ok(usage);
+        }
+        let usage = usage::commits_usage(&self.repo, &commits)?;
+        self.core().usage.insert(commits, usage);
+        Ok(usage)
+    }
+    #[instrument(level = "debug", skip(self, reply), fields(path))]
+    fn statfs(&self, ino: u64, reply: ReplyStatfs) {
+        self.record_ino(ino);
+        match self.usage(ino) {
+            Ok(used) => {
+                let free = usage::repo_free(&self.repo);
+                usage::reply_statfs(reply, used, free);
+            }
+            Err(err) => reply.error(log_errno("statfs error", &err)),
+        }
+    }
+    // 卸载时输出缓存命中率
+    fn destroy(&self) {
+        let stats = self.core().cache.stats();
+        info!(
+            attr_hits = stats.attr_hits,
+            attr_misses = stats.attr_misses,
+            attr_hit_rate = stats.attr_hit_rate(),
+            dir_hits = stats.dir_hits,
+            dir_misses = stats.dir_misses,
+            dir_hit_rate = stats.dir_hit_rate(),
+            "cache stats"
+        );
+    }
+    // 取出 opendir 时保存的目录列表，返回 (.. 的 inode, 列表)
+    fn dir_handle(&self, ino: u64, fh: u64) -> Result<(u64, DirEntries), c_int> {
+        self.record_ino(ino);
+        let slot = self.handle(fh).ok_or(EBADF)?;
+        let handle = slot.lock();
+        match &*handle {
+            Handle::Dir { parent, entries } => Ok((*parent, entries.clone())),
+            _ => Err(ENOTDIR),
+        }
+    }
+    // 读取目录
+    #[instrument(level = "debug", skip(self, reply), fields(path))]
+    fn readdir(&self, ino: u64, fh: u64, offset: i64, mut reply: ReplyDirectory) {
+        if offset < 0 {
+            return reply.error(EINVAL);
+        }
+        let (parent, entries) = match self.dir_handle(ino, fh) {
+            Ok(handle) => handle,
+            Err(code) => return reply.error(code),
+        };
+        for (i, (ino, kind, name)) in listing(ino, parent, &entries).skip(offset as usize) {
+            let next = i as i64 + 1;
+            trace!(ino, offset = next, ?kind, ?name, "add entry");
+            if reply.add(ino, next, kind, name) {
+                trace!("reply buffer full");
+                break;
+            }
+        }
+        reply.ok();
+    }
+    // 读取目录，同时返回每一项的属性，省去内核逐个 lookup
+    #[instrument(level = "debug", skip(self, reply), fields(path))]
+    fn readdirplus(&self, ino: u64, fh: u64, offset: i64, mut reply: ReplyDirectoryPlus) {
+        if offset < 0 {
+            return reply.error(EINVAL);
+        }
+        let (parent, entries) = match self.dir_handle(ino, fh) {
+            Ok(handle) => handle,
+            Err(code) => return reply.error(code),
+        };
+        for (i, (ino, kind, name)) in listing(ino, parent, &entries).skip(offset as usize) {
+            let next = i as i64 + 1;
+            let node = self.core().ino_node(ino);
+            let attr = node.and_then(|node| {
+                let attr = self.node_attr(&node)?;
+                Ok((attr, self.node_ttl(&node)))
+            });
+            // 查询失败的项跳过，不影响其他项的偏移量
+            let (attr, ttl) = match attr {
+                Ok(attr) => attr,
+                Err(err) => {
+                    log_errno("query info error", &err);
+                    continue;
+                }
+            };
+            trace!(ino, offset = next, ?kind, ?name, "add entry");
+            if reply.add(ino, next, name, &ttl, &attr, 0) {
+                trace!("reply buffer full");
+                break;
+            }
+        }
+        reply.ok();
+    }
+    // 没有开启 default_permissions 时在这里按提交中的权限检查，内核不会在 lookup、open
+    // 和 opendir 之前调用 access
+    fn permit(&self, node: &Node, caller: Caller, mask: i32) -> Result<(), c_int> {
+        if self.options.default_permissions {
+            return Ok(());
+        }
+        let attr = self
+            .node_attr(node)
+            .map_err(|err| log_errno("query info error", &err))?;
+        check_access(&attr, caller.uid, mask, |gid| caller.in_group(gid))
+    }
+    // 打开目录，列出的内容保存在句柄中，之后的 readdir 从句柄中分页读取
+    #[instrument(level = "debug", skip(self, reply), fields(path))]
+    fn opendir(&self, ino: u64, flags: i32, caller: Caller, reply: ReplyOpen) {
+        let node = self.core().ino_node(ino);
+        let node = match node {
+            Ok(node) => node,
+            Err(err) => return reply.error(log_errno("get node error", &err)),
+        };
+        self.record_path(&node);
+        if let Err(code) = self.permit(&node, caller, libc::R_OK) {
+            return reply.error(code);
+        }
+        let entries = self
+            .dir_entries(&node)
+            .and_then(|entries| Ok((self.parent_ino(&node)?, entries)));
+        match entries {
+            Ok((parent, entries)) => {
+                let fh = self.add_handle(Handle::Dir { parent, entries });
+                reply.opened(fh, 0);
+            }
+            Err(err) => reply.error(log_errno("list dir error", &err)),
+        }
+    }
+    // 定位目录内的文件
+    #[instrument(level = "debug", skip(self, reply), fields(path))]
+    fn lookup(&self, parent: u64, name: &OsStr, caller: Caller, reply: ReplyEntry) {
+        let parent = self.core().ino_node(parent);
+        let parent = match parent {
+            Ok(parent) => parent,
+            Err(err) => return reply.error(log_errno("get node error", &err)),
+        };
+        // 在目录中查找需要目录的执行权限
+        if let Err(code) = self.permit(&parent, caller, libc::X_OK) {
+            return reply.error(code);
+        }
+        let node = match self.child_node(&parent, name) {
+            Ok(node) => node,
+            Err(err) => return reply.error(log_errno("lookup error", &err)),
+        };
+        // 根据节点获取文件信息
+        self.record_path(&node);
+        match self.node_attr(&node) {
+            Ok(attr) => reply.entry(&self.node_ttl(&node), &attr, 0),
+            Err(err) => reply.error(log_errno("query info error", &err)),
+        }
+    }
+    //  获取文件属性
+    #[instrument(level = "debug", skip(self, reply), fields(path))]
+    fn getattr(&self, ino: u64, reply: ReplyAttr) {
+        let node = self.core().ino_node(ino);
+        let node = match node {
+            Ok(node) => node,
+            Err(err) => return reply.error(log_errno("get node error", &err)),
+        };
+        self.record_path(&node);
+        match self.node_attr(&node) {
+            Ok(attr) => reply.attr(&self.node_ttl(&node), &attr),
+            Err(err) => reply.error(log_errno("query info error", &err)),
+        }
+    }
+    // 读取符号链接
+    #[instrument(level = "debug", skip(self, reply), fields(path))]
+    fn readlink(&self, ino: u64, reply: ReplyData) {
+        self.record_ino(ino);
+        let f = match self.ino_file(ino) {
+            Ok(f) => f,
+            Err(err) => return reply.error(log_errno("get file error", &err)),
+        };
+        let info = match f.query_info("standard::symlink-target", FLAGS_NONE, CANCEL_NONE) {
+            Ok(info) => info,
+            Err(err) => return reply.error(log_errno("query info error", &err)),
+        };
+        match info.symlink_target() {
+            Some(target) => reply.data(target.as_os_str().as_bytes()),
+            None => reply.error(EINVAL),
+        }
+    }
+    // 获取扩展属性
+    #[instrument(level = "debug", skip(self, reply), fields(path))]
+    fn getxattr(&self, ino: u64, name: &OsStr, size: u32, reply: ReplyXattr) {
+        self.record_ino(ino);
+        let xattrs = match self.xattrs(ino) {
+            Ok(xattrs) => xattrs,
+            Err(err) => return reply.error(log_errno("get xattrs error", &err)),
+        };
+        let value = xattrs
+            .into_iter()
+            .find(|(n, _)| n.as_slice() == name.as_bytes());
+        match value {
+            Some((_, value)) => reply_xattr(reply, size, &value),
+            None => reply.error(ENODATA),
+        }
+    }
+    // 列出扩展属性，名称之间以 \0 分隔
+    #[instrument(level = "debug", skip(self, reply), fields(path))]
+    fn listxattr(&self, ino: u64, size: u32, reply: ReplyXattr) {
+        self.record_ino(ino);
+        let xattrs = match self.xattrs(ino) {
+            Ok(xattrs) => xattrs,
+            Err(err) => return reply.error(log_errno("get xattrs error", &err)),
+        };
+        let mut names = vec![];
+        for (name, _) in xattrs {
+            names.extend_from_slice(&name);
+            names.push(0);
+        }
+        reply_xattr(reply, size, &names);
+    }
+    // 打开文件，句柄中保持打开的文件流；内容不会变化，让内核保留页缓存
+    #[instrument(level = "debug", skip(self, reply), fields(path))]
+    fn open(&self, ino: u64, flags: i32, caller: Caller, reply: ReplyOpen) {
+        self.record_ino(ino);
+        if flags & libc::O_ACCMODE != libc::O_RDONLY || flags & libc::O_TRUNC != 0 {
+            return reply.error(EROFS);
+        }
+        let node = self.core().ino_node(ino);
+        let node = match node {
+            Ok(node) => node,
+            Err(err) => return reply.error(log_errno("get node error", &err)),
+        };
+        if let Err(code) = self.permit(&node, caller, libc::R_OK) {
+            return reply.error(code);
+        }
+        match self.open_handle(node) {
+            Ok(handle) => {
+                let fh = self.add_handle(handle);
+                reply.opened(fh, FOPEN_KEEP_CACHE);
+            }
+            Err(err) => reply.error(log_errno("open error", &err)),
+        }
+    }
+    // 按提交中的权限位和所有者检查访问权限，挂载点只读，不允许写
+    #[instrument(level = "debug", skip(self, reply), fields(path))]
+    fn access(&self, ino: u64, caller: Caller, mask: i32, reply: ReplyEmpty) {
+        self.record_ino(ino);
+        let node = self.core().ino_node(ino);
+        let attr = node.and_then(|node| self.node_attr(&node));
+        match attr {
+            Ok(attr) => match check_access(&attr, caller.uid, mask, |gid| caller.in_group(gid)) {
+                Ok(()) => reply.ok(),
+                Err(code) => reply.error(code),
+            },
+            Err(err) => reply.error(log_errno("query info error", &err)),
+        }
+    }
+    // 读取文件，只锁住当前句柄，不同句柄的读取并发进行
+    #[instrument(level = "debug", skip(self, reply), fields(path))]
+    fn read(&self, ino: u64, fh: u64, offset: i64, size: u32, reply: ReplyData) {
+        self.record_ino(ino);
+        if offset < 0 {
+            return reply.error(EINVAL);
+        }
+        let slot = match self.handle(fh) {
+            Some(slot) => slot,
+            None => return reply.error(EBADF),
+        };
+        let offset = offset as u64;
+        let mut handle = slot.lock();
+        // 内核开启了 FUSE_ASYNC_READ，同一句柄的多个预读请求会同时发出，线程池不保证处理顺序。
+        // 不支持 seek 的流先等前面的请求读到 offset，避免跳过中间内容后再重新打开从头读；
+        // 等待有上限，前面的请求还在队列中排队时不会占住所有工作线程
+        let behind = |handle: &mut Handle| match handle {
+            Handle::Stream(cursor) => cursor.pos < offset && !cursor.can_seek(),
+            _ => false,
+        };
+        if behind(&mut handle) {
+            handle = match slot.moved.wait_timeout_while(handle, READ_WAIT, behind) {
+                Ok((handle, _)) => handle,
+                Err(err) => err.into_inner().0,
+            };
+        }
+        match &mut *handle {
+            Handle::Stream(cursor) => {
+                // 不支持 seek 的流不能向前定位，需要读取 offset 之前的内容时重新打开
+                if cursor.pos > offset && !cursor.can_seek() {
+                    let file = self.ino_file(ino);
+                    let reopened = file.and_then(|f| open_stream(&f));
+                    match reopened {
+                        Ok(reopened) => *cursor = reopened,
+                        Err(err) => return reply.error(log_errno("open stream error", &err)),
+                    }
+                }
+                let data = cursor.seek(offset).and_then(|_| cursor.read(size as usize));
+                drop(handle);
+                slot.moved.notify_all();
+                match data {
+                    Ok(data) => reply.data(&data),
+                    Err(err) => reply.error(log_errno("read stream error", &err)),
+                }
+            }
+            Handle::Data(data) => {
+                let start = (offset as usize).min(data.len());
+                let end = start.saturating_add(size as usize).min(data.len());
+                reply.data(&data[start..end]);
+            }
+            Handle::Dir { .. } => reply.error(EISDIR),
+        }
+    }
+}
+
+// 请求在线程池中处理，参数中的引用先复制一份
+impl Filesystem for OstreeFs {
+    fn destroy(&mut self) {
+        // 等待线程池中的请求处理完
+        self.pool.take();
+        self.shared.destroy();
+    }
+    fn statfs(&mut self, _req: &Request, ino: u64, reply: ReplyStatfs) {
+        self.spawn(move |shared| shared.statfs(ino, reply));
+    }
+    fn init(&mut self, _req: &Request, config: &mut KernelConfig) -> Result<(), c_int> {
+        // 让内核用 readdirplus 一次取得目录项和属性
+        if let Err(unsupported) = config.add_capabilities(FUSE_DO_READDIRPLUS) {
+            warn!(unsupported, "kernel does not support readdirplus");
+        }
+        Ok(())
+    }
+    fn readdir(&mut self, _req: &Request, ino: u64, fh: u64, offset: i64, reply: ReplyDirectory) {
+        self.spawn(move |shared| shared.readdir(ino, fh, offset, reply));
+    }
+    fn readdirplus(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        reply: ReplyDirectoryPlus,
+    ) {
+        self.spawn(move |shared| shared.readdirplus(ino, fh, offset, reply));
+    }
+    fn opendir(&mut self, req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
+        let caller = Caller::new(req);
+        self.spawn(move |shared| shared.opendir(ino, flags, caller, reply));
+    }
+    fn releasedir(&mut self, _req: &Request, _ino: u64, fh: u64, _flags: i32, reply: ReplyEmpty) {
+        self.shared.remove_handle(fh);
+        reply.ok();
+    }
+    fn lookup(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let name = name.to_os_string();
+        let caller = Caller::new(req);
+        self.spawn(move |shared| shared.lookup(parent, &name, caller, reply));
+    }
+    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
+        self.spawn(move |shared| shared.getattr(ino, reply));
+    }
+    fn readlink(&mut self, _req: &Request, ino: u64, reply: ReplyData) {
+        self.spawn(move |shared| shared.readlink(ino, reply));
+    }
+    fn getxattr(&mut self, _req: &Request, ino: u64, name: &OsStr, size: u32, reply: ReplyXattr) {
+        let name = name.to_os_string();
+        self.spawn(move |shared| shared.getxattr(ino, &name, size, reply));
+    }
+    fn listxattr(&mut self, _req: &Request, ino: u64, size: u32, reply: ReplyXattr) {
+        self.spawn(move |shared| shared.listxattr(ino, size, reply));
+    }
+    fn open(&mut self, req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
+        let caller = Caller::new(req);
+        self.spawn(move |shared| shared.open(ino, flags, caller, reply));
+    }
+    fn release(
+        &mut self,
+        _req: &Request,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        self.shared.remove_handle(fh);
+        reply.ok();
+    }
+    fn read(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock: Option<u64>,
+        reply: ReplyData,
+    ) {
+        self.spawn(move |shared| shared.read(ino, fh, offset, size, reply));
+    }
+    fn access(&mut self, req: &Request, ino: u64, mask: i32, reply: ReplyEmpty) {
+        let caller = Caller::new(req);
+        self.spawn(move |shared| shared.access(ino, caller, mask, reply));
+    }
+
+    // 提交不可修改，所有修改操作都返回 EROFS
+    fn setattr(
+        &mut self,
+        _req: &Request,
+        _ino: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        _size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        reply.error(EROFS);
+    }
+    fn mknod(
+        &mut self,
+        _req: &Request,
+        _parent: u64,
+        _name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        _rdev: u32,
+        reply: ReplyEntry,
+    ) {
+        reply.error(EROFS);
+    }
+    fn mkdir(
+        &mut self,
+        _req: &Request,
+        _parent: u64,
+        _name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        reply.error(EROFS);
+    }
+    fn unlink(&mut self, _req: &Request, _parent: u64, _name: &OsStr, reply: ReplyEmpty) {
+        reply.error(EROFS);
+    }
+    fn rmdir(&mut self, _req: &Request, _parent: u64, _name: &OsStr, reply: ReplyEmpty) {
+        reply.error(EROFS);
+    }
+    fn symlink(
+        &mut self,
+        _req: &Request,
+        _parent: u64,
+        _link_name: &OsStr,
+        _target: &Path,
+        reply: ReplyEntry,
+    ) {
+        reply.error(EROFS);
+    }
+    fn rename(
+        &mut self,
+        _req: &Request,
+        _parent: u64,
+        _name: &OsStr,
+        _newparent: u64,
+        _newname: &OsStr,
+        _flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        reply.error(EROFS);
+    }
+    fn link(
+        &mut self,
+        _req: &Request,
+        _ino: u64,
+        _newparent: u64,
+        _newname: &OsStr,
+        reply: ReplyEntry,
+    ) {
+        reply.error(EROFS);
+    }
+    fn write(
+        &mut self,
+        _req: &Request,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+        _data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        reply.error(EROFS);
+    }
+    fn create(
+        &mut self,
+        _req: &Request,
+        _parent: u64,
+        _name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        _flags: i32,
+        reply: ReplyCreate,
+    ) {
+        reply.error(EROFS);
+    }
+    fn fallocate(
+        &mut self,
+        _req: &Request,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+        _length: i64,
+        _mode: i32,
+        reply: ReplyEmpty,
+    ) {
+        reply.error(EROFS);
+    }
+    fn setxattr(
+        &mut self,
+        _req: &Request,
+        _ino: u64,
+        _name: &OsStr,
+        _value: &[u8],
+        _flags: i32,
+        _position: u32,
+        reply: ReplyEmpty,
+    ) {
+        reply.error(EROFS);
+    }
+    fn removexattr(&mut self, _req: &Request, _ino: u64, _name: &OsStr, reply: ReplyEmpty) {
+        reply.error(EROFS);
+    }
+}
+
+// 发出请求的进程，附加组只在需要检查组权限时才读取
+#[derive(Clone, Copy, Debug)]
+struct Caller {
+    uid: u32,
+    gid: u32,
+    pid: u32,
+}
+
+impl Caller {
+    fn new(req: &Request) -> Caller {
+        Caller {
+            uid: req.uid(),
+            gid: req.gid(),
+            pid: req.pid(),
+        }
+    }
+    // 主组或附加组中是否有 gid，和内核的 default_permissions 一样考虑附加组
+    fn in_group(&self, gid: u32) -> bool {
+        gid == self.gid || proc_groups(self.pid).contains(&gid)
+    }
+}
+
+// 进程的附加组，来自 /proc/<pid>/status 的 Groups 行；进程已经退出时为空
+fn proc_groups(pid: u32) -> Vec<u32> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).unwrap_or_default();
+    parse_groups(&status)
+}
+
+fn parse_groups(status: &str) -> Vec<u32> {
+    let groups = status.lines().find_map(|line| line.strip_prefix("Groups:"));
+    groups
+        .map(|groups| {
+            groups
+                .split_whitespace()
+                .filter_map(|g| g.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// 按 owner/group/other 的权限位检查 mask，in_group 判断调用者是否属于某个组；root 可以读取
+// 任何文件，但只能执行至少有一个执行位的文件；写权限总是被拒绝
+fn check_access(
+    attr: &FileAttr,
+    uid: u32,
+    mask: i32,
+    in_group: impl FnOnce(u32) -> bool,
+) -> Result<(), c_int> {
+    if mask & libc::W_OK != 0 {
+        return Err(EROFS);
+    }
+    let mask = (mask & (libc::R_OK | libc::X_OK)) as u16;
+    if mask == 0 {
+        return Ok(());
+    }
+    if uid == 0 {
+        let executable = attr.kind == FileType::Directory || attr.perm & 0o111 != 0;
+        if mask & libc::X_OK as u16 == 0 || executable {
+            return Ok(());
+        }
+        return Err(EACCES);
+    }
+    let bits = if uid == attr.uid {
+        attr.perm >> 6
+    } else if in_group(attr.gid) {
+        attr.perm >> 3
+    } else {
+        attr.perm
+    };
+    if bits & mask == mask {
+        Ok(())
+    } else {
+        Err(EACCES)
+    }
+}
+
+// 文件类型优先取 unix::mode 中的 S_IFMT，gio 的文件类型不区分设备文件、管道和套接字
+fn file_kind(info: &gio::FileInfo) -> FileType {
+    if info.has_attribute("unix::mode") {
+        match info.attribute_uint32("unix::mode") & libc::S_IFMT {
+            libc::S_IFDIR => return FileType::Directory,
+            libc::S_IFLNK => return FileType::Symlink,
+            libc::S_IFREG => return FileType::RegularFile,
+            libc::S_IFCHR => return FileType::CharDevice,
+            libc::S_IFBLK => return FileType::BlockDevice,
+            libc::S_IFIFO => return FileType::NamedPipe,
+            libc::S_IFSOCK => return FileType::Socket,
+            _ => {}
+        }
+    }
+    match info.file_type() {
+        gio::FileType::Directory => FileType::Directory,
+        gio::FileType::SymbolicLink => FileType::Symlink,
+        _ => FileType::RegularFile,
+    }
+}
+
+// 仓库的本地路径，用于显示
+fn repo_path(repo: &ostree::Repo) -> String {
+    match repo.path().path() {
+        Some(path) => path.display().to_string(),
+        None => repo.path().uri().to_string(),
+    }
+}
+
+// 目录的完整列表，. 和 .. 在前；偏移量是条目在其中的序号，分页读取时从 offset 处继续
+fn listing(
+    ino: u64,
+    parent: u64,
+    entries: &DirEntries,
+) -> impl Iterator<Item = (usize, (u64, FileType, &OsStr))> {
+    let dots = [(ino, "."), (parent, "..")]
+        .into_iter()
+        .map(|(ino, name)| (ino, FileType::Directory, OsStr::new(name)));
+    let children = entries
+        .iter()
+        .map(|(ino, kind, name)| (*ino, *kind, name.as_os_str()));
+    dots.chain(children).enumerate()
+}
+
+// 与 dirtree 中的顺序一致，先按名称的字节序排列文件，再排列子目录；
+// 每次列出的顺序相同，分页读取时偏移量不变
+fn sort_entries(entries: &mut [(u64, FileType, OsString)]) {
+    entries.sort_by(|a, b| {
+        let a = (a.1 == FileType::Directory, &a.2);
+        let b = (b.1 == FileType::Directory, &b.2);
+        a.cmp(&b)
+    });
+}
+
+fn open_stream(f: &gio::File) -> Result<Cursor, glib::Error> {
+    Ok(Cursor::new(f.read(CANCEL_NONE)?.upcast()))
+}
+
+// 记录错误并返回对应的 errno，文件不存在是正常情况，只在 debug 级别记录
+fn log_errno(context: &str, err: &glib::Error) -> c_int {
+    let code = errno(err);
+    if code == ENOENT {
+        debug!("{}: {}", context, err);
+    } else {
+        warn!("{}: {}", context, err);
+    }
+    code
+}
+
+// 拼接父目录路径和文件名，根目录为 ""
+fn join_path(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", parent, name)
+    }
+}
+
+// 按字节拼接提交中的路径，name 为空时返回 parent 本身
+fn join_os(parent: &Path, name: &OsStr) -> PathBuf {
+    if name.is_empty() {
+        parent.to_path_buf()
+    } else {
+        parent.join(name)
+    }
+}
+
+// size 为 0 时只返回长度，缓冲区不够时返回 ERANGE
+fn reply_xattr(reply: ReplyXattr, size: u32, data: &[u8]) {
+    if size == 0 {
+        reply.size(data.len() as u32);
+    } else if data.len() > size as usize {
+        reply.error(ERANGE);
+    } else {
+        reply.data(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 测试用的临时仓库，drop 时删除
+    struct TestRepo {
+        dir: PathBuf,
+        repo: ostree::Repo,
+    }
+
+    impl TestRepo {
+        fn new(name: &str) -> TestRepo {
+            let dir =
+                std::env::temp_dir().join(format!("ostree-fuse-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            let repo = ostree::Repo::new_for_path(dir.join("repo"));
+            repo.create(ostree::RepoMode::Archive, CANCEL_NONE).unwrap();
+            TestRepo { dir, repo }
+        }
+        // 提交 (路径, 内容) 列出的文件，中间的目录自动创建，返回提交校验和
+        fn commit(&self, files: &[(&str, &str)]) -> String {
+            self.commit_on(None, files)
+        }
+        fn commit_on(&self, parent: Option<&str>, files: &[(&str, &str)]) -> String {
+            let src = self.dir.join("src");
+            let _ = std::fs::remove_dir_all(&src);
+            for (path, content) in files {
+                let path = src.join(path);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, content).unwrap();
+            }
+            let mtree = ostree::MutableTree::new();
+            self.repo.prepare_transaction(CANCEL_NONE).unwrap();
+            self.repo
+                .write_directory_to_mtree(&gio::File::for_path(&src), &mtree, None, CANCEL_NONE)
+                .unwrap();
+            let root = self.repo.write_mtree(&mtree, CANCEL_NONE).unwrap();
+            let root = root.downcast::<ostree::RepoFile>().unwrap();
+            let checksum = self
+                .repo
+                .write_commit(parent, Some("test"), None, None, &root, CANCEL_NONE)
+                .unwrap();
+            self.repo.commit_transaction(CANCEL_NONE).unwrap();
+            checksum.to_string()
+        }
+        fn set_ref(&self, name: &str, checksum: &str) {
+            self.repo.prepare_transaction(CANCEL_NONE).unwrap();
+            self.repo.transaction_set_ref(None, name, Some(checksum));
+            self.repo.commit_transaction(CANCEL_NONE).unwrap();
+        }
+    }
+
+    impl Drop for TestRepo {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    // 目录列表中名为 name 的项的 inode
+    fn entry_ino(shared: &Shared, dir: &Node, name: &str) -> u64 {
+        shared
+            .dir_entries(dir)
+            .unwrap()
+            .iter()
+            .find(|(_, _, n)| n == name)
+            .map(|(ino, _, _)| *ino)
+            .unwrap()
+    }
+
+    #[test]
+    fn nested_paths_resolve_relative_to_parent() {
+        let test = TestRepo::new("nested");
+        let checksum = test.commit(&[("c", "root"), ("a/c", "middle"), ("a/b/c", "deep")]);
+        let fs = OstreeFs::new(test.repo.clone(), &checksum, Options::default()).unwrap();
+        let shared = &fs.shared;
+        let root = shared.core().ino_node(1).unwrap();
+
+        // 逐级 lookup a/b/c
+        let mut node = root.clone();
+        for name in ["a", "b", "c"] {
+            node = shared.child_node(&node, OsStr::new(name)).unwrap();
+        }
+        assert_eq!(shared.core().node_path(&node), PathBuf::from("a/b/c"));
+        let deep = shared.node_attr(&node).unwrap();
+
+        // 每一级目录中的 c 是不同的 inode，与 lookup 得到的一致
+        let a = shared.child_node(&root, OsStr::new("a")).unwrap();
+        let b = shared.child_node(&a, OsStr::new("b")).unwrap();
+        let root_c = entry_ino(shared, &root, "c");
+        let a_c = entry_ino(shared, &a, "c");
+        let b_c = entry_ino(shared, &b, "c");
+        assert_eq!(b_c, deep.ino);
+        assert_ne!(root_c, a_c);
+        assert_ne!(root_c, b_c);
+        assert_ne!(a_c, b_c);
+
+        // 列目录时分配的 inode 指回各自的路径和内容
+        let expected = [
+            (root_c, "c", "root"),
+            (a_c, "a/c", "middle"),
+            (b_c, "a/b/c", "deep"),
+        ];
+        for (ino, path, content) in expected {
+            let node = shared.core().ino_node(ino).unwrap();
+            assert_eq!(shared.core().node_path(&node), PathBuf::from(path));
+            let mut cursor = open_stream(&shared.node_file(&node).unwrap()).unwrap();
+            assert_eq!(cursor.read(64).unwrap(), content.as_bytes());
+        }
+    }
+
+    #[test]
+    fn ref_roots_follow_the_ref() {
+        let test = TestRepo::new("moving-ref");
+        let first = test.commit(&[("f", "first")]);
+        test.set_ref("main", &first);
+        let fs = OstreeFs::with_refs(test.repo.clone(), false, Options::default()).unwrap();
+        let shared = &fs.shared;
+        let refs = shared.core().ino_node(1).unwrap();
+        let old = shared.child_node(&refs, OsStr::new("main")).unwrap();
+        let old_ino = shared.node_attr(&old).unwrap().ino;
+
+        let second = test.commit(&[("f", "second")]);
+        test.set_ref("main", &second);
+        let main = shared.child_node(&refs, OsStr::new("main")).unwrap();
+        let main_ino = shared.node_attr(&main).unwrap().ino;
+        assert_ne!(main_ino, old_ino);
+        assert_eq!(entry_ino(shared, &refs, "main"), main_ino);
+
+        // <ref>、<ref>/.ostree/checksum 和 <ref>/history/0 都是新的提交
+        let meta = shared.child_node(&main, OsStr::new(META)).unwrap();
+        let checksum = shared.child_node(&meta, OsStr::new("checksum")).unwrap();
+        let Node::MetaFile { root, name } = checksum else {
+            panic!("not a metadata file")
+        };
+        let text = shared.meta_file(root, &name).unwrap();
+        assert_eq!(text, format!("{}\n", second).into_bytes());
+        let history = shared.child_node(&main, OsStr::new(HISTORY)).unwrap();
+        let latest = shared.child_node(&history, OsStr::new("0")).unwrap();
+        let Node::Tree { root, .. } = latest else {
+            panic!("not a commit root")
+        };
+        assert_eq!(shared.root_checksum(root).unwrap(), second);
+        assert_eq!(shared.parent_ino(&history).unwrap(), main_ino);
+
+        // 之前分配的 inode 仍然指向原来的提交
+        let Node::Tree { root, .. } = old else {
+            panic!("not a commit root")
+        };
+        assert_eq!(shared.root_checksum(root).unwrap(), first);
+    }
+
+    #[test]
+    fn history_lookup_stops_at_n_and_listing_is_cached() {
+        let test = TestRepo::new("history");
+        let first = test.commit(&[("f", "1")]);
+        let second = test.commit_on(Some(&first), &[("f", "2")]);
+        let third = test.commit_on(Some(&second), &[("f", "3")]);
+        test.set_ref("main", &third);
+        let fs = OstreeFs::with_refs(test.repo.clone(), false, Options::default()).unwrap();
+        let shared = &fs.shared;
+
+        // 只读到第 n 个提交，不缓存不完整的历史
+        assert_eq!(
+            *shared.history("main", 1).unwrap(),
+            vec![third.clone(), second.clone()]
+        );
+        assert!(shared.core().histories.is_empty());
+
+        let refs = shared.core().ino_node(1).unwrap();
+        let main = shared.child_node(&refs, OsStr::new("main")).unwrap();
+        let history = shared.child_node(&main, OsStr::new(HISTORY)).unwrap();
+        let names: Vec<OsString> = shared
+            .dir_entries(&history)
+            .unwrap()
+            .iter()
+            .map(|(_, _, name)| name.clone())
+            .collect();
+        assert_eq!(names, ["0", "1", "2"]);
+        let cached = shared.core().histories.get(&third).cloned();
+        assert_eq!(*cached.unwrap(), vec![third, second.clone(), first]);
+
+        let one = shared.child_node(&history, OsStr::new("1")).unwrap();
+        let Node::Tree { root, .. } = one else {
+            panic!("not a commit root")
+        };
+        assert_eq!(shared.root_checksum(root).unwrap(), second);
+        assert!(shared.child_node(&history, OsStr::new("3")).is_err());
+    }
+
+    #[test]
+    fn links_are_counted_when_a_file_is_stated() {
+        let test = TestRepo::new("lazy-links");
+        let first = test.commit(&[("a", "same"), ("b", "same")]);
+        let second = test.commit_on(Some(&first), &[("a", "same"), ("d/b", "same")]);
+        test.set_ref("main", &second);
+        let options = Options {
+            inode_mode: InodeMode::Checksum,
+            ..Options::default()
+        };
+        let fs = OstreeFs::with_refs(test.repo.clone(), false, options).unwrap();
+        let shared = &fs.shared;
+
+        // 查询 history 下各提交根目录的属性不遍历提交
+        let refs = shared.core().ino_node(1).unwrap();
+        let main = shared.child_node(&refs, OsStr::new("main")).unwrap();
+        let history = shared.child_node(&main, OsStr::new(HISTORY)).unwrap();
+        for (ino, _, _) in shared.dir_entries(&history).unwrap().iter() {
+            let node = shared.core().ino_node(*ino).unwrap();
+            assert_eq!(shared.node_attr(&node).unwrap().kind, FileType::Directory);
+        }
+        assert!(shared.core().links.is_empty());
+
+        // 查询文件属性时才统计它所在的提交
+        let old = shared.child_node(&history, OsStr::new("1")).unwrap();
+        let a = shared.child_node(&old, OsStr::new("a")).unwrap();
+        assert_eq!(shared.node_attr(&a).unwrap().nlink, 2);
+        let core = shared.core();
+        assert_eq!(core.links.keys().collect::<Vec<_>>(), [&first]);
+    }
+
+    fn files(names: &[&str]) -> DirEntries {
+        let entries = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (10 + i as u64, FileType::RegularFile, OsString::from(name)))
+            .collect();
+        Arc::new(entries)
+    }
+
+    fn collect(
+        ino: u64,
+        parent: u64,
+        entries: &DirEntries,
+        offset: usize,
+        limit: usize,
+    ) -> Vec<(usize, u64, OsString)> {
+        listing(ino, parent, entries)
+            .skip(offset)
+            .take(limit)
+            .map(|(i, (ino, _, name))| (i, ino, name.to_os_string()))
+            .collect()
+    }
+
+    #[test]
+    fn listing_starts_with_dots() {
+        let entries = files(&["a", "b"]);
+        let list = collect(5, 1, &entries, 0, usize::MAX);
+        assert_eq!(
+            list,
+            vec![
+                (0, 5, OsString::from(".")),
+                (1, 1, OsString::from("..")),
+                (2, 10, OsString::from("a")),
+                (3, 11, OsString::from("b")),
+            ]
+        );
+    }
+
+    #[test]
+    fn listing_offsets_are_stable_across_pages() {
+        let names: Vec<String> = (0..50).map(|i| format!("f{:02}", i)).collect();
+        let names: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+        let entries = files(&names);
+        let full = collect(5, 1, &entries, 0, usize::MAX);
+        assert_eq!(full.len(), 52);
+        // 每页最多 7 项，下一页从上一页最后一项的 offset（序号加 1）继续
+        let mut paged = vec![];
+        let mut offset = 0;
+        loop {
+            let page = collect(5, 1, &entries, offset, 7);
+            let Some(last) = page.last() else { break };
+            offset = last.0 + 1;
+            paged.extend(page);
+        }
+        assert_eq!(paged, full);
+        // 从任意 offset 继续时都得到同一项
+        for (offset, entry) in full.iter().enumerate() {
+            let page = collect(5, 1, &entries, offset, 1);
+            assert_eq!(page, vec![entry.clone()]);
+        }
+        assert!(collect(5, 1, &entries, full.len(), 1).is_empty());
+    }
+
+    #[test]
+    fn entries_sort_files_before_directories() {
+        let mut entries = vec![
+            (1, FileType::Directory, OsString::from("b")),
+            (2, FileType::RegularFile, OsString::from("c")),
+            (3, FileType::Directory, OsString::from("a")),
+            (4, FileType::Symlink, OsString::from("B")),
+            (5, FileType::RegularFile, OsString::from("a")),
+        ];
+        sort_entries(&mut entries);
+        let order: Vec<u64> = entries.iter().map(|(ino, _, _)| *ino).collect();
+        assert_eq!(order, vec![4, 5, 2, 3, 1]);
+    }
+
+    fn attr(kind: FileType, perm: u16) -> FileAttr {
+        FileAttr {
+            ino: 2,
+            size: 0,
+            blksize: 512,
+            blocks: 0,
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind,
+            perm,
+            nlink: 1,
+            uid: 1000,
+            gid: 100,
+            rdev: 0,
+            flags: 0,
+        }
+    }
+
+    fn check(attr: &FileAttr, uid: u32, groups: &[u32], mask: i32) -> Result<(), c_int> {
+        check_access(attr, uid, mask, |gid| groups.contains(&gid))
+    }
+
+    #[test]
+    fn access_uses_owner_group_other_bits() {
+        let file = attr(FileType::RegularFile, 0o640);
+        assert_eq!(check(&file, 1000, &[100], libc::R_OK), Ok(()));
+        assert_eq!(check(&file, 1000, &[100], libc::X_OK), Err(EACCES));
+        assert_eq!(check(&file, 1001, &[100], libc::R_OK), Ok(()));
+        assert_eq!(
+            check(&file, 1001, &[100], libc::R_OK | libc::X_OK),
+            Err(EACCES)
+        );
+        assert_eq!(check(&file, 1001, &[101], libc::R_OK), Err(EACCES));
+        assert_eq!(check(&file, 1001, &[101], libc::F_OK), Ok(()));
+        // 所有者只看 owner 位，即使 group 位允许
+        let file = attr(FileType::RegularFile, 0o070);
+        assert_eq!(check(&file, 1000, &[100], libc::R_OK), Err(EACCES));
+        assert_eq!(check(&file, 1001, &[100], libc::R_OK), Ok(()));
+    }
+
+    #[test]
+    fn access_counts_supplementary_groups() {
+        let file = attr(FileType::RegularFile, 0o750);
+        assert_eq!(
+            check(&file, 1001, &[101, 100], libc::R_OK | libc::X_OK),
+            Ok(())
+        );
+        assert_eq!(check(&file, 1001, &[101, 102], libc::R_OK), Err(EACCES));
+    }
+
+    #[test]
+    fn groups_from_proc_status() {
+        let status = "Name:\tcat\nGid:\t100\t100\t100\t100\nGroups:\t10 100 1001 \nNgid:\t0\n";
+        assert_eq!(parse_groups(status), vec![10, 100, 1001]);
+        assert!(parse_groups("Name:\tcat\nGroups:\t\n").is_empty());
+        assert!(parse_groups("").is_empty());
+    }
+
+    #[test]
+    fn root_executes_only_with_an_execute_bit() {
+        let file = attr(FileType::RegularFile, 0o000);
+        assert_eq!(check(&file, 0, &[0], libc::R_OK), Ok(()));
+        assert_eq!(check(&file, 0, &[0], libc::X_OK), Err(EACCES));
+        let file = attr(FileType::RegularFile, 0o001);
+        assert_eq!(check(&file, 0, &[0], libc::R_OK | libc::X_OK), Ok(()));
+        let dir = attr(FileType::Directory, 0o000);
+        assert_eq!(check(&dir, 0, &[0], libc::X_OK), Ok(()));
+    }
+
+    #[test]
+    fn write_access_is_read_only() {
+        let file = attr(FileType::RegularFile, 0o777);
+        assert_eq!(check(&file, 1000, &[100], libc::W_OK), Err(EROFS));
+        assert_eq!(check(&file, 0, &[0], libc::R_OK | libc::W_OK), Err(EROFS));
+    }
+}
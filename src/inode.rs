@@ -0,0 +1,66 @@
+use gio::glib;
+use gio::prelude::*;
+use gio::FileInfo;
+use std::collections::HashMap;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use crate::dirtree::DirTree;
+
+// 取校验和的前 64 位作为 inode，避开 0 和根目录的 1
+pub(crate) fn csum_ino(checksum: &str) -> u64 {
+    let ino = u64::from_str_radix(checksum.get(..16).unwrap_or(checksum), 16).unwrap_or(0);
+    ino.max(2)
+}
+
+// 由路径计算 inode，用于没有对象校验和的节点，按字节计算以支持非 UTF-8 的路径
+pub(crate) fn path_ino(path: &Path) -> u64 {
+    let bytes = path.as_os_str().as_bytes();
+    match glib::compute_checksum_for_data(glib::ChecksumType::Sha256, bytes) {
+        Some(checksum) => csum_ino(&checksum),
+        None => 2,
+    }
+}
+
+// 文件使用内容对象的校验和，相同内容的文件共享 inode；
+// 目录不能出现在多个位置，使用路径和 dirtree/dirmeta 校验和一起计算
+pub(crate) fn checksum_ino(path: &Path, file: &gio::File, info: &FileInfo) -> u64 {
+    let repo_file = file.downcast_ref::<ostree::RepoFile>();
+    let checksum = match repo_file {
+        Some(f) if f.ensure_resolved().is_ok() => {
+            if info.file_type() == gio::FileType::Directory {
+                let contents = f.tree_get_contents_checksum().map(|c| c.to_string());
+                let metadata = f.tree_get_metadata_checksum().map(|c| c.to_string());
+                let mut key = path.as_os_str().as_bytes().to_vec();
+                for checksum in [contents, metadata] {
+                    key.push(0);
+                    key.extend_from_slice(checksum.unwrap_or_default().as_bytes());
+                }
+                glib::compute_checksum_for_data(glib::ChecksumType::Sha256, &key)
+            } else {
+                Some(f.checksum())
+            }
+        }
+        _ => None,
+    };
+    match checksum {
+        Some(checksum) => csum_ino(&checksum),
+        None => path_ino(path),
+    }
+}
+
+// 统计 dirtree 下每个内容对象被引用的次数，即共享 inode 的硬链接数
+pub(crate) fn count_links(
+    repo: &ostree::Repo,
+    dirtree: &str,
+    links: &mut HashMap<u64, u32>,
+) -> Result<(), glib::Error> {
+    let tree = DirTree::load(repo, dirtree)?;
+    for (_, checksum) in tree.files() {
+        *links.entry(csum_ino(&checksum)).or_insert(0) += 1;
+    }
+    for (_, checksum) in tree.dirs() {
+        count_links(repo, &checksum, links)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,163 @@
+//! 以 FUSE 方式只读挂载 OSTree 仓库中的提交
+
+mod cache;
+mod cursor;
+mod dirtree;
+mod errno;
+mod fs;
+mod inode;
+mod meta;
+mod pool;
+mod usage;
+
+use fuser::{BackgroundSession, MountOption};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+pub use cache::CacheStats;
+pub use fs::OstreeFs;
+
+pub(crate) const FLAGS_NONE: gio::FileQueryInfoFlags = gio::FileQueryInfoFlags::NONE;
+pub(crate) const CANCEL_NONE: Option<&gio::Cancellable> = gio::Cancellable::NONE;
+
+/// 默认的内核缓存时间，提交不会变化，相当于永久缓存
+pub const DEFAULT_TTL: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// 默认缓存的属性和目录列表条目数
+pub const DEFAULT_CACHE_SIZE: usize = 16384;
+
+/// 挂载选项
+#[derive(Clone)]
+pub struct Options {
+    /// 提交中的 uid 到挂载后 uid 的映射，未列出的保持不变
+    pub uid_map: HashMap<u32, u32>,
+    /// 提交中的 gid 到挂载后 gid 的映射，未列出的保持不变
+    pub gid_map: HashMap<u32, u32>,
+    pub inode_mode: InodeMode,
+    /// 属性缓存和目录列表缓存各自最多保存的条目数，0 表示不缓存
+    pub cache_size: usize,
+    /// 内核缓存提交中文件的目录项和属性的时间
+    pub ttl: Duration,
+    /// 处理请求的工作线程数
+    pub threads: usize,
+    /// 由内核按文件的权限位和所有者检查访问权限；关闭时由文件系统在 lookup、open、opendir
+    /// 和 access 中检查
+    pub default_permissions: bool,
+    /// 允许挂载者以外的用户访问，非 root 用户需要在 /etc/fuse.conf 中开启 user_allow_other
+    pub allow_other: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            uid_map: HashMap::new(),
+            gid_map: HashMap::new(),
+            inode_mode: InodeMode::default(),
+            cache_size: DEFAULT_CACHE_SIZE,
+            ttl: DEFAULT_TTL,
+            threads: default_threads(),
+            default_permissions: false,
+            allow_other: false,
+        }
+    }
+}
+
+/// inode 分配方式
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InodeMode {
+    /// 按访问顺序递增分配
+    #[default]
+    Sequential,
+    /// 由对象校验和计算，多次挂载结果一致，内容相同的文件共享 inode
+    Checksum,
+}
+
+/// 默认的工作线程数，与 CPU 核数相同
+pub fn default_threads() -> usize {
+    thread::available_parallelism().map_or(4, |n| n.get())
+}
+
+// 文件系统在 /proc/mounts 中显示为 fuse.ostree，来源是仓库路径和挂载的提交
+fn mount_options(fs: &OstreeFs) -> Vec<MountOption> {
+    let mut options = vec![
+        MountOption::RO,
+        MountOption::FSName(fs.source().to_string()),
+        MountOption::Subtype("ostree".to_string()),
+        MountOption::AutoUnmount,
+    ];
+    if fs.options().default_permissions {
+        options.push(MountOption::DefaultPermissions);
+    }
+    if fs.options().allow_other {
+        options.push(MountOption::AllowOther);
+    }
+    options
+}
+
+/// 把 fs 挂载到 mountpoint，阻塞直到被卸载
+pub fn mount<P: AsRef<Path>>(fs: OstreeFs, mountpoint: P) -> io::Result<()> {
+    let options = mount_options(&fs);
+    fuser::mount2(fs, mountpoint, &options)
+}
+
+/// 在后台线程把 fs 挂载到 mountpoint，返回的句柄被 drop 时卸载
+pub fn spawn_mount<P: AsRef<Path>>(fs: OstreeFs, mountpoint: P) -> io::Result<MountHandle> {
+    let options = mount_options(&fs);
+    let session = fuser::spawn_mount2(fs, mountpoint.as_ref(), &options)?;
+    Ok(MountHandle {
+        mountpoint: mountpoint.as_ref().to_path_buf(),
+        session,
+    })
+}
+
+/// 后台挂载的文件系统，drop 时卸载
+pub struct MountHandle {
+    mountpoint: PathBuf,
+    session: BackgroundSession,
+}
+
+impl MountHandle {
+    pub fn mountpoint(&self) -> &Path {
+        &self.mountpoint
+    }
+
+    /// 文件系统是否已经被外部卸载，例如执行了 fusermount -u
+    pub fn is_finished(&self) -> bool {
+        self.session.guard.is_finished()
+    }
+
+    /// 卸载文件系统，返回时挂载点已经卸载
+    pub fn unmount(self) {
+        drop(self);
+    }
+
+    /// 阻塞直到收到 SIGINT/SIGTERM 或文件系统被外部卸载，然后卸载；
+    /// 返回会话线程的结果，会话出错退出时返回它的错误
+    pub fn wait(self) -> io::Result<()> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut ids = vec![];
+        for signal in [SIGINT, SIGTERM] {
+            ids.push(signal_hook::flag::register(signal, stop.clone())?);
+        }
+        while !stop.load(Ordering::Relaxed) && !self.is_finished() {
+            thread::sleep(Duration::from_millis(100));
+        }
+        for id in ids {
+            signal_hook::low_level::unregister(id);
+        }
+        // 先卸载，会话线程读到 ENODEV 后退出，再等待它的结果
+        let guard = {
+            let session = self.session;
+            session.guard
+        };
+        guard
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("fuse session thread panicked")))
+    }
+}
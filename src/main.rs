@@ -1,290 +1,306 @@
-use fuser::{
-    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry,
-    Request,
-};
-use gio::prelude::FileExt;
-use gio::FileInfo;
-use libc::ENOENT;
-use ostree;
-use std::collections::HashMap;
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use ostree_fuse::{InodeMode, Options, OstreeFs, DEFAULT_CACHE_SIZE, DEFAULT_TTL};
 use std::error::Error;
-use std::ffi::OsStr;
-use std::os::unix::fs::DirEntryExt;
-use std::time::{Duration, UNIX_EPOCH};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::PathBuf;
+use std::process;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::info;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 
-const TTL: Duration = Duration::from_secs(1); // 1 second
-const FLAGS_NONE: gio::FileQueryInfoFlags = gio::FileQueryInfoFlags::NONE;
-const CANCEL_NONE: Option<&gio::Cancellable> = gio::Cancellable::NONE;
-const HELLO_DIR_ATTR: FileAttr = FileAttr {
-    ino: 1,
-    size: 0,
-    blocks: 0,
-    atime: UNIX_EPOCH, // 1970-01-01 00:00:00
-    mtime: UNIX_EPOCH,
-    ctime: UNIX_EPOCH,
-    crtime: UNIX_EPOCH,
-    kind: FileType::Directory,
-    perm: 0o755,
-    nlink: 2,
-    uid: 501,
-    gid: 20,
-    rdev: 0,
-    flags: 0,
-    blksize: 512,
-};
-const HELLO_TXT_CONTENT: &str = "Hello World!\n";
-const HELLO_TXT_ATTR: FileAttr = FileAttr {
-    ino: 2,
-    size: 13,
-    blocks: 1,
-    atime: UNIX_EPOCH, // 1970-01-01 00:00:00
-    mtime: UNIX_EPOCH,
-    ctime: UNIX_EPOCH,
-    crtime: UNIX_EPOCH,
-    kind: FileType::RegularFile,
-    perm: 0o644,
-    nlink: 1,
-    uid: 501,
-    gid: 20,
-    rdev: 0,
-    flags: 0,
-    blksize: 512,
-};
+#[derive(Parser)]
+#[command(
+    name = "ostree-fuse",
+    version,
+    about = "Mount OSTree commits read-only via FUSE"
+)]
+struct Cli {
+    /// Log level: off, error, warn, info, debug or trace; at debug every
+    /// filesystem operation is logged with its inode, path and latency
+    #[arg(long, global = true, value_name = "LEVEL", default_value_t = LevelFilter::WARN)]
+    log_level: LevelFilter,
+    /// Append log output to FILE instead of stderr; needed to keep logs with --daemon
+    #[arg(long, global = true, value_name = "FILE")]
+    log_file: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
 
-struct MyFS {
-    ostree_file: gio::File,
-    inoMap: HashMap<u64, String>,
-    pathMap: HashMap<String, u64>,
-    inoIndex: u64,
+#[derive(Subcommand)]
+enum Command {
+    /// Mount a ref or commit of a repository
+    Mount(MountArgs),
 }
 
-impl Filesystem for MyFS {
-    // 读取目录
-    fn readdir(
-        &mut self,
-        _req: &Request,
-        ino: u64,
-        _fh: u64,
-        offset: i64,
-        mut reply: ReplyDirectory,
-    ) {
-        println!("readdir ino:{} offset:{}", ino, offset);
-        let file = if ino == 1 {
-            self.ostree_file.clone()
-        } else {
-            let path = self.inoMap.get(&ino);
-            if path.is_none() {
-                println!("get path by ino error {}", ino);
-                return reply.error(ENOENT);
-            }
-            self.ostree_file.resolve_relative_path(path.unwrap())
-        };
-        let children = file.enumerate_children("", FLAGS_NONE, CANCEL_NONE);
-        if children.is_err() {
-            println!("enumerate_children err({}): {:?}", ino, children.err());
-            return reply.error(ENOENT);
+#[derive(Args)]
+#[command(group(ArgGroup::new("rev").required(true).args(["ref_", "commit", "all_refs"])))]
+struct MountArgs {
+    /// Path of the OSTree repository
+    #[arg(long, value_name = "PATH")]
+    repo: PathBuf,
+    /// Ref to mount, resolved to its current commit
+    #[arg(long = "ref", value_name = "REF")]
+    ref_: Option<String>,
+    /// Commit checksum to mount
+    #[arg(long, value_name = "CHECKSUM")]
+    commit: Option<String>,
+    /// Mount every ref of the repository as a subdirectory of the mountpoint,
+    /// with its history under <ref>/history/<n> and any commit under by-commit/<checksum>
+    #[arg(long)]
+    all_refs: bool,
+    /// With --all-refs, also list remote refs, named remote:ref
+    #[arg(long, requires = "all_refs")]
+    remotes: bool,
+    /// Report files owned by uid FROM in the commit as owned by TO (repeatable)
+    #[arg(long = "uid-map", value_name = "FROM:TO", value_parser = parse_id_map)]
+    uid_map: Vec<(u32, u32)>,
+    /// Report files owned by gid FROM in the commit as owned by TO (repeatable)
+    #[arg(long = "gid-map", value_name = "FROM:TO", value_parser = parse_id_map)]
+    gid_map: Vec<(u32, u32)>,
+    /// How inode numbers are assigned
+    #[arg(long, value_enum, default_value_t = InodesArg::Sequential)]
+    inodes: InodesArg,
+    /// Number of file attributes and directory listings to cache each, 0 disables caching
+    #[arg(long, value_name = "ENTRIES", default_value_t = DEFAULT_CACHE_SIZE)]
+    cache_size: usize,
+    /// Seconds the kernel may cache entries and attributes of files in a commit;
+    /// ref listings and history are always revalidated after a second
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_TTL.as_secs())]
+    ttl: u64,
+    /// Number of worker threads handling requests [default: number of CPUs]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    threads: Option<u64>,
+    /// Let the kernel check permissions against file modes and owners
+    #[arg(long)]
+    default_permissions: bool,
+    /// Allow users other than the mounting user to access the mount
+    /// (needs user_allow_other in /etc/fuse.conf unless run as root)
+    #[arg(long)]
+    allow_other: bool,
+    /// Stay in the foreground until unmounted or interrupted (default)
+    #[arg(long, conflicts_with = "daemon")]
+    foreground: bool,
+    /// Detach into the background once the filesystem is mounted; stderr is then
+    /// closed, so logs are kept only with --log-file
+    #[arg(long)]
+    daemon: bool,
+    /// Existing directory to mount on
+    mountpoint: PathBuf,
+}
+
+/// How inode numbers are assigned
+#[derive(Clone, Copy, ValueEnum)]
+enum InodesArg {
+    /// Hand out increasing numbers in the order entries are looked up
+    Sequential,
+    /// Derive numbers from object checksums, stable across mounts; files with the same content share an inode
+    Checksum,
+}
+
+impl From<InodesArg> for InodeMode {
+    fn from(arg: InodesArg) -> InodeMode {
+        match arg {
+            InodesArg::Sequential => InodeMode::Sequential,
+            InodesArg::Checksum => InodeMode::Checksum,
         }
-        let mut i = offset;
-        for info in children.unwrap().skip(offset as usize) {
-            if info.is_err() {
-                println!("children err {} {:?}", ino, info.err());
-                return reply.error(ENOENT);
-            }
-            let info = info.unwrap();
-            let path = format!("/{}", info.name().to_str().unwrap().to_string());
+    }
+}
 
-            let path_ino = self.pathMap.get(&path);
-            let ino = if path_ino.is_none() {
-                self.inoIndex += 1;
-                self.inoIndex.clone()
-            } else {
-                path_ino.unwrap().clone()
-            };
+fn parse_id_map(s: &str) -> Result<(u32, u32), String> {
+    let (from, to) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected FROM:TO, got {}", s))?;
+    let from = from
+        .parse()
+        .map_err(|_| format!("invalid id {:?} in {}", from, s))?;
+    let to = to
+        .parse()
+        .map_err(|_| format!("invalid id {:?} in {}", to, s))?;
+    Ok((from, to))
+}
 
-            let attr = info2attr(&info, ino);
-            i = i + 1;
-            println!(
-                "add ino:{} offset:{} kind:{:?} name:{:?}",
-                attr.ino,
-                i,
-                attr.kind,
-                info.name()
-            );
-            self.inoMap.insert(ino, path.clone());
-            self.pathMap.insert(path.clone(), ino);
-            let ok = reply.add(attr.ino, i as i64, attr.kind, info.name());
-            if !ok {
-                println!("reply add failed");
-                break;
+fn main() {
+    let cli = Cli::parse();
+    // 日志写到 stderr 或 --log-file 指定的文件，span 关闭时输出耗时
+    let writer = match &cli.log_file {
+        Some(path) => match fs::OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => BoxMakeWriter::new(Mutex::new(file)),
+            Err(err) => {
+                eprintln!("error: failed to open log file {}: {}", path.display(), err);
+                process::exit(1);
             }
-        }
-        return reply.ok();
+        },
+        None => BoxMakeWriter::new(io::stderr),
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(cli.log_level)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_ansi(cli.log_file.is_none())
+        .with_writer(writer)
+        .init();
+    // 后台运行时 stderr 被重定向到 /dev/null，没有日志文件时日志会丢失
+    let Command::Mount(args) = &cli.command;
+    if args.daemon && cli.log_file.is_none() && cli.log_level > LevelFilter::OFF {
+        eprintln!("warning: logs are discarded after detaching, use --log-file to keep them");
+    }
+    let ret = match cli.command {
+        Command::Mount(args) => mount(args),
+    };
+    if let Err(err) = ret {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+// 解析 ref 或提交，挂载对应的目录树
+fn mount(args: MountArgs) -> Result<(), Box<dyn Error>> {
+    if !args.repo.is_dir() {
+        return Err(format!("repo {} is not a directory", args.repo.display()).into());
     }
-    // 定位目录内的文件
-    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
-        println!("lookup {} {:?}", parent, name.to_str());
-        // 获取完整的path
-        let mut path = name.to_str().unwrap().to_string();
-        if parent != 1 {
-            let parent_file = self.inoMap.get(&parent);
-            if parent_file.is_none() {
-                return reply.error(ENOENT);
+    if !args.mountpoint.is_dir() {
+        return Err(format!(
+            "mountpoint {} is not a directory",
+            args.mountpoint.display()
+        )
+        .into());
+    }
+    // 在打开仓库之前 fork，避免子进程继承 GLib 的状态
+    let ready = if args.daemon && !args.foreground {
+        Some(daemonize()?)
+    } else {
+        None
+    };
+    let repo = ostree::Repo::new_for_path(&args.repo);
+    repo.open(gio::Cancellable::NONE)
+        .map_err(|e| format!("failed to open repo {}: {}", args.repo.display(), e))?;
+    let options = Options {
+        uid_map: args.uid_map.into_iter().collect(),
+        gid_map: args.gid_map.into_iter().collect(),
+        inode_mode: args.inodes.into(),
+        cache_size: args.cache_size,
+        ttl: Duration::from_secs(args.ttl),
+        threads: args
+            .threads
+            .map_or_else(ostree_fuse::default_threads, |n| n as usize),
+        default_permissions: args.default_permissions,
+        allow_other: args.allow_other,
+    };
+    let filesystem = if args.all_refs {
+        let filesystem = OstreeFs::with_refs(repo, args.remotes, options)
+            .map_err(|e| format!("failed to list refs: {}", e))?;
+        info!(
+            refs = filesystem.refs().map_or(0, |refs| refs.len()),
+            mountpoint = %args.mountpoint.display(),
+            "mount ostree refs"
+        );
+        filesystem
+    } else {
+        let rev = match (args.ref_, args.commit) {
+            (Some(name), _) => repo
+                .resolve_rev(&name, false)
+                .map_err(|e| format!("failed to resolve ref {}: {}", name, e))?
+                .ok_or_else(|| format!("ref {} not found", name))?
+                .to_string(),
+            (None, Some(checksum)) => {
+                ostree::validate_checksum_string(&checksum)
+                    .map_err(|e| format!("invalid commit checksum {}: {}", checksum, e))?;
+                checksum
             }
-            path = format!("{}/{}", parent_file.unwrap(), path);
-        }
-        // 根据path获取文件信息
-        println!("lookup {}", path);
-        let f = self.ostree_file.resolve_relative_path(&path);
-        let info = f.query_info("", FLAGS_NONE, CANCEL_NONE);
-        if info.is_err() {
-            println!("query info err {:?}", info.err());
-            return reply.error(ENOENT);
-        }
-        let path_ino = self.pathMap.get(&path);
-        let ino = if path_ino.is_none() {
-            self.inoIndex += 1;
-            self.inoIndex.clone()
-        } else {
-            path_ino.unwrap().clone()
+            (None, None) => unreachable!("clap requires --ref, --commit or --all-refs"),
         };
-        self.inoMap.insert(ino, path.clone());
-        self.pathMap.insert(path.clone(), ino);
-        let attr = info2attr(&info.unwrap(), ino);
-        return reply.entry(&TTL, &attr, 0);
+        info!(
+            commit = %rev,
+            mountpoint = %args.mountpoint.display(),
+            "mount ostree commit"
+        );
+        OstreeFs::new(repo, &rev, options)
+            .map_err(|e| format!("failed to read commit {}: {}", rev, e))?
+    };
+
+    let handle = ostree_fuse::spawn_mount(filesystem, &args.mountpoint)?;
+    if let Some(ready) = ready {
+        detach(ready)?;
     }
-    //  获取文件属性
-    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
-        println!("getattr {}", ino);
-        if ino == 1 {
-            let info = self.ostree_file.query_info("", FLAGS_NONE, CANCEL_NONE);
-            let attr = info2attr(&info.unwrap(), ino);
-            return reply.attr(&TTL, &attr);
-        }
-        let path = self.inoMap.get(&ino);
-        if path.is_none() {
-            println!("ino map none {}", ino);
-            return reply.error(ENOENT);
-        }
-        let f = self.ostree_file.resolve_relative_path(path.unwrap());
-        let info = f.query_info("", FLAGS_NONE, CANCEL_NONE);
-        if info.is_err() {
-            println!("query info error({}): {:?}", path.unwrap(), info.err());
-            return reply.error(ENOENT);
-        }
-        let attr = info2attr(&info.unwrap(), ino);
-        return reply.attr(&TTL, &attr);
+    handle.wait()?;
+    Ok(())
+}
+
+// fork 出子进程在后台完成挂载，父进程等子进程通过管道报告挂载成功后退出；
+// 挂载成功前子进程仍然使用终端，出错时错误直接打印出来
+fn daemonize() -> Result<fs::File, Box<dyn Error>> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error().into());
     }
-    // 读取文件
-    fn read(
-        &mut self,
-        _req: &Request,
-        ino: u64,
-        _fh: u64,
-        offset: i64,
-        _size: u32,
-        _flags: i32,
-        _lock: Option<u64>,
-        reply: ReplyData,
-    ) {
-        println!("read {}", ino);
-        let path = self.inoMap.get(&ino);
-        if path.is_none() {
-            println!("ino map none {}", ino);
-            return reply.error(ENOENT);
+    let (mut reader, writer) =
+        unsafe { (fs::File::from_raw_fd(fds[0]), fs::File::from_raw_fd(fds[1])) };
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error().into()),
+        0 => {
+            drop(reader);
+            unsafe { libc::setsid() };
+            Ok(writer)
         }
-        let f = self.ostree_file.resolve_relative_path(path.unwrap());
-        let data = f.load_bytes(CANCEL_NONE);
-        if data.is_err() {
-            println!("load bytes {}", ino);
-            return reply.error(ENOENT);
+        _ => {
+            drop(writer);
+            let mut status = String::new();
+            let _ = reader.read_to_string(&mut status);
+            process::exit(if status == "ok" { 0 } else { 1 });
         }
-        reply.data(&data.unwrap().0);
     }
 }
 
-fn main() {
-    let ret = refs();
-    if ret.is_err() {
-        println!("{:?}", ret.err())
+// 通知父进程挂载成功，并把标准输入输出重定向到 /dev/null
+fn detach(mut ready: fs::File) -> io::Result<()> {
+    ready.write_all(b"ok")?;
+    drop(ready);
+    let null = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    for fd in 0..3 {
+        unsafe { libc::dup2(null.as_raw_fd(), fd) };
     }
+    Ok(())
 }
 
-fn refs() -> Result<String, Box<dyn Error>> {
-    let repo = ostree::Repo::new_for_path("repo");
-    let cancel = gio::Cancellable::NONE;
-    // let flags = gio::FileQueryInfoFlags::NONE;
-    repo.open(cancel)?;
-    let refs = repo.list_refs(None, cancel)?;
-    for (key, val) in refs {
-        println!("mount ostree branch:{} id:{}", key, val);
-        let f = repo.read_commit(key.as_str(), cancel)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let mountpoint = "/tmp/rootfs";
-        let mut options = vec![MountOption::RO, MountOption::FSName("hello".to_string())];
-        options.push(MountOption::AutoUnmount);
-        let mut filesystem = MyFS {
-            ostree_file: f.0,
-            inoMap: HashMap::new(),
-            pathMap: HashMap::new(),
-            inoIndex: 1,
-        };
-        filesystem.inoMap.insert(1, "".to_string());
-        filesystem.pathMap.insert("".to_string(), 1);
-        fuser::mount2(filesystem, &mountpoint, &options).unwrap();
-        break;
+    #[test]
+    fn id_map() {
+        assert_eq!(parse_id_map("0:1000"), Ok((0, 1000)));
+        assert_eq!(parse_id_map("4294967295:0"), Ok((u32::MAX, 0)));
     }
-    return Ok("".to_string());
-}
 
-fn info2attr(info: &gio::FileInfo, ino: u64) -> FileAttr {
-    let mut size = info.size() as u64;
-    if size == 0 {
-        size = 4096
+    #[test]
+    fn id_map_without_colon() {
+        assert_eq!(
+            parse_id_map("1000"),
+            Err("expected FROM:TO, got 1000".to_string())
+        );
     }
-    return FileAttr {
-        ino: ino,
-        size: size,
-        blksize: 512,
-        blocks: 0,
-        atime: info.modification_time(),
-        mtime: info.modification_time(),
-        ctime: info.modification_time(),
-        kind: match info.file_type() {
-            gio::FileType::Directory => FileType::Directory,
-            _ => FileType::RegularFile,
-        },
-        crtime: info.modification_time(),
-        perm: match info.file_type() {
-            gio::FileType::Directory => 0o755,
-            _ => 0o644,
-        },
-        nlink: 0,
-        uid: 1000,
-        gid: 1000,
-        rdev: 0,
-        flags: 0,
-    };
-}
 
-fn print_dir(dir: gio::File) {
-    let cancel = gio::Cancellable::NONE;
-    let children = dir.enumerate_children("", gio::FileQueryInfoFlags::NONE, cancel);
-    for info in children.unwrap() {
-        let info = info.unwrap();
-        let t = info.file_type();
-        if gio::FileType::Directory == t {
-            println!(
-                "dir: {} {}",
-                dir.path().unwrap().to_str().unwrap(),
-                info.name().to_str().unwrap()
-            );
-            print_dir(dir.resolve_relative_path(info.name()))
-        } else {
-            println!(
-                "file: {}/{}",
-                dir.path().unwrap().to_str().unwrap(),
-                info.name().to_str().unwrap()
-            );
-        }
+    #[test]
+    fn id_map_with_invalid_ids() {
+        assert_eq!(
+            parse_id_map("root:0"),
+            Err(r#"invalid id "root" in root:0"#.to_string())
+        );
+        assert_eq!(
+            parse_id_map("0:-1"),
+            Err(r#"invalid id "-1" in 0:-1"#.to_string())
+        );
+        assert_eq!(
+            parse_id_map("0:"),
+            Err(r#"invalid id "" in 0:"#.to_string())
+        );
+        assert!(parse_id_map("0:1:2").is_err());
     }
 }
@@ -0,0 +1,117 @@
+use gio::glib;
+
+// 提交根目录下的元数据目录及其中的文件
+pub(crate) const META: &str = ".ostree";
+pub(crate) const META_FILES: [&str; 6] = [
+    "checksum",
+    "subject",
+    "body",
+    "timestamp",
+    "parent",
+    "metadata.json",
+];
+
+// 生成 .ostree 下名为 name 的文件内容，commit 类型为 (a{sv}aya(say)sstayay)，
+// 依次是元数据、父提交、相关对象、标题、正文、时间戳和根目录
+pub(crate) fn commit_file(commit: &glib::Variant, checksum: &str, name: &str) -> Option<String> {
+    let line = |s: &str| {
+        if s.is_empty() {
+            "".to_string()
+        } else {
+            format!("{}\n", s)
+        }
+    };
+    let text = match name {
+        "checksum" => line(checksum),
+        "subject" => line(commit.child_value(3).str().unwrap_or("")),
+        "body" => line(commit.child_value(4).str().unwrap_or("")),
+        // 时间戳以大端序存储
+        "timestamp" => {
+            let timestamp = u64::from_be(commit.child_value(5).get::<u64>().unwrap_or(0));
+            line(&timestamp.to_string())
+        }
+        "parent" => line(ostree::commit_get_parent(commit).as_deref().unwrap_or("")),
+        "metadata.json" => {
+            let mut json = String::new();
+            variant_json(&commit.child_value(0), &mut json);
+            line(&json)
+        }
+        _ => return None,
+    };
+    Some(text)
+}
+
+// 把 GVariant 转换成 JSON，键为字符串的字典转为对象，其他容器转为数组
+fn variant_json(v: &glib::Variant, out: &mut String) {
+    match v.classify() {
+        glib::VariantClass::Boolean => out.push_str(&v.get::<bool>().unwrap_or(false).to_string()),
+        glib::VariantClass::Byte => out.push_str(&v.get::<u8>().unwrap_or(0).to_string()),
+        glib::VariantClass::Int16 => out.push_str(&v.get::<i16>().unwrap_or(0).to_string()),
+        glib::VariantClass::Uint16 => out.push_str(&v.get::<u16>().unwrap_or(0).to_string()),
+        glib::VariantClass::Int32 => out.push_str(&v.get::<i32>().unwrap_or(0).to_string()),
+        glib::VariantClass::Uint32 => out.push_str(&v.get::<u32>().unwrap_or(0).to_string()),
+        glib::VariantClass::Int64 => out.push_str(&v.get::<i64>().unwrap_or(0).to_string()),
+        glib::VariantClass::Uint64 => out.push_str(&v.get::<u64>().unwrap_or(0).to_string()),
+        glib::VariantClass::Double => match v.get::<f64>() {
+            Some(d) if d.is_finite() => out.push_str(&d.to_string()),
+            _ => out.push_str("null"),
+        },
+        glib::VariantClass::String
+        | glib::VariantClass::ObjectPath
+        | glib::VariantClass::Signature => json_string(v.str().unwrap_or(""), out),
+        glib::VariantClass::Variant => match v.as_variant() {
+            Some(inner) => variant_json(&inner, out),
+            None => out.push_str("null"),
+        },
+        glib::VariantClass::Maybe => {
+            if v.n_children() == 0 {
+                out.push_str("null");
+            } else {
+                variant_json(&v.child_value(0), out);
+            }
+        }
+        glib::VariantClass::Array
+            if v.type_().element().is_dict_entry()
+                && v.type_().element().key() == glib::VariantTy::STRING =>
+        {
+            out.push('{');
+            for i in 0..v.n_children() {
+                if i > 0 {
+                    out.push(',');
+                }
+                let entry = v.child_value(i);
+                json_string(entry.child_value(0).str().unwrap_or(""), out);
+                out.push(':');
+                variant_json(&entry.child_value(1), out);
+            }
+            out.push('}');
+        }
+        glib::VariantClass::Array | glib::VariantClass::Tuple | glib::VariantClass::DictEntry => {
+            out.push('[');
+            for i in 0..v.n_children() {
+                if i > 0 {
+                    out.push(',');
+                }
+                variant_json(&v.child_value(i), out);
+            }
+            out.push(']');
+        }
+        _ => out.push_str("null"),
+    }
+}
+
+fn json_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
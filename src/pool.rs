@@ -0,0 +1,71 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+// 固定大小的线程池，fuser 的会话线程只负责分发请求，由工作线程处理并回复
+pub(crate) struct Pool {
+    sender: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Pool {
+    pub(crate) fn new(threads: usize) -> Pool {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..threads.max(1))
+            .map(|i| {
+                let receiver = receiver.clone();
+                thread::Builder::new()
+                    .name(format!("ostree-fuse-{}", i))
+                    .spawn(move || work(&receiver))
+            })
+            .filter_map(Result::ok)
+            .collect();
+        Pool {
+            sender: Some(sender),
+            workers,
+        }
+    }
+    // 提交任务，没有可用的工作线程时在当前线程执行
+    pub(crate) fn execute<F: FnOnce() + Send + 'static>(&self, job: F) {
+        let job: Job = Box::new(job);
+        let job = match &self.sender {
+            Some(sender) if !self.workers.is_empty() => match sender.send(job) {
+                Ok(()) => return,
+                Err(mpsc::SendError(job)) => job,
+            },
+            _ => job,
+        };
+        job();
+    }
+}
+
+fn work(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        // 只在取任务时持有锁，任务在锁外执行
+        let job = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+        // 任务 panic 时未回复的请求由 fuser 回复 EIO，工作线程继续处理后面的任务
+        match job {
+            Ok(job) => {
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+// drop 时关闭队列，等待已提交的任务执行完
+impl Drop for Pool {
+    fn drop(&mut self) {
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use gio::glib;
+use std::collections::HashSet;
+use std::mem;
+
+use crate::dirtree::DirTree;
+use crate::CANCEL_NONE;
+
+// statfs 报告的块大小
+pub(crate) const BLOCK_SIZE: u32 = 4096;
+const NAME_MAX: u32 = 255;
+
+// 提交占用的空间，相同的对象只统计一次
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Usage {
+    pub(crate) bytes: u64,
+    pub(crate) objects: u64,
+}
+
+// 统计提交中所有内容对象和 dirtree 对象在仓库中占用的空间，多个提交共享的对象只统计一次
+pub(crate) fn commits_usage(repo: &ostree::Repo, commits: &[String]) -> Result<Usage, glib::Error> {
+    let mut usage = Usage::default();
+    let mut seen = HashSet::new();
+    for checksum in commits {
+        let (commit, _) = repo.load_commit(checksum)?;
+        // 提交的第 6 项是根目录的 dirtree 校验和
+        let dirtree = ostree::checksum_from_bytes_v(&commit.child_value(6));
+        walk(repo, &dirtree, &mut seen, &mut usage)?;
+    }
+    Ok(usage)
+}
+
+fn walk(
+    repo: &ostree::Repo,
+    dirtree: &str,
+    seen: &mut HashSet<String>,
+    usage: &mut Usage,
+) -> Result<(), glib::Error> {
+    if !seen.insert(dirtree.to_string()) {
+        return Ok(());
+    }
+    usage.bytes +=
+        repo.query_object_storage_size(ostree::ObjectType::DirTree, dirtree, CANCEL_NONE)?;
+    usage.objects += 1;
+    let tree = DirTree::load(repo, dirtree)?;
+    for (_, checksum) in tree.files() {
+        if seen.insert(checksum.clone()) {
+            usage.bytes +=
+                repo.query_object_storage_size(ostree::ObjectType::File, &checksum, CANCEL_NONE)?;
+            usage.objects += 1;
+        }
+    }
+    for (_, checksum) in tree.dirs() {
+        walk(repo, &checksum, seen, usage)?;
+    }
+    Ok(())
+}
+
+// 仓库所在文件系统的剩余空间，返回 (空闲块数, 空闲 inode 数)，块大小为 BLOCK_SIZE
+pub(crate) fn repo_free(repo: &ostree::Repo) -> (u64, u64) {
+    let mut st: libc::statvfs = unsafe { mem::zeroed() };
+    if unsafe { libc::fstatvfs(repo.dfd(), &mut st) } != 0 {
+        return (0, 0);
+    }
+    // 64 位 Linux 上 statvfs 的各项都是 u64
+    let free = st.f_bavail * st.f_frsize / u64::from(BLOCK_SIZE);
+    (free, st.f_favail)
+}
+
+// 已用空间是提交的大小，剩余空间是仓库所在文件系统的剩余空间
+pub(crate) fn reply_statfs(reply: fuser::ReplyStatfs, used: Usage, free: (u64, u64)) {
+    let blocks = used.bytes.div_ceil(BLOCK_SIZE as u64);
+    let (bfree, ffree) = free;
+    reply.statfs(
+        blocks + bfree,
+        bfree,
+        bfree,
+        used.objects + ffree,
+        ffree,
+        BLOCK_SIZE,
+        NAME_MAX,
+        BLOCK_SIZE,
+    );
+}